@@ -0,0 +1,565 @@
+use crate::conf::*;
+use crate::database::{self, ExportResult, Playlist, SqliteStorage, Storage, Version};
+use crate::spotify::{self, get_spotify_client_from_client_credentials};
+use crate::{diff, feed, items, metrics, notify, retention, schedule};
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Local};
+use log::{error, info};
+use rspotify::{AuthCodeSpotify, ClientCredsSpotify};
+use rspotify::model::PlaylistId;
+use rspotify::prelude::Id;
+use serde::Serialize;
+use serde_json::Value;
+use single_instance::SingleInstance;
+use url::Url;
+
+/// Configuration of archify, read by the CLI from `data/config.json`.
+#[non_exhaustive]
+pub struct ArchifyConf{
+	/// Client id of the Spotify application.
+	pub archify_id: String,
+	/// Client secret of the Spotify application.
+	pub archify_secret: String,
+	/// Global retention policy, overridden by the policy of a playlist.
+	pub retention: retention::RetentionPolicy,
+	/// Global update schedule, overridden by the schedule of a playlist. Without any, playlists are due at every update.
+	pub schedule: Option<String>,
+	pub notifications: Vec<notify::Sink>,
+	/// Directory the feeds are written to after an update.
+	pub feeds: Option<String>,
+	/// File the metrics are written to after an update, for the textfile collector of the node exporter.
+	pub metrics_textfile: Option<String>,
+	/// Token required by `serve` in every request.
+	pub serve_token: Option<String>
+}
+
+impl ArchifyConf {
+	/// Configuration with the credentials of a Spotify application and default values for everything else.
+	pub fn new(archify_id: &str, archify_secret: &str) -> ArchifyConf {
+		ArchifyConf {
+			archify_id: String::from(archify_id),
+			archify_secret: String::from(archify_secret),
+			retention: retention::RetentionPolicy::default(),
+			schedule: None,
+			notifications: Vec::new(),
+			feeds: None,
+			metrics_textfile: None,
+			serve_token: None
+		}
+	}
+
+	/// Reads the configuration from the content of a `config.json` file.
+	///
+	/// ```
+	/// use archify::ArchifyConf;
+	///
+	/// let json = serde_json::json!({ "archify_id": "id", "archify_secret": "secret", "schedule": "6h" });
+	/// let conf = ArchifyConf::from_json(&json).unwrap();
+	/// assert_eq!(conf.schedule.as_deref(), Some("6h"));
+	/// ```
+	pub fn from_json(json: &Value) -> Result<ArchifyConf, String> {
+		let (archify_id, archify_secret) = match (json[CONF_ARCHIFY_ID].as_str(), json[CONF_ARCHIFY_SECRET].as_str()) {
+			(Some(id), Some(secret)) => (id, secret),
+			_ => return Err(format!("{} and {} are required.", CONF_ARCHIFY_ID, CONF_ARCHIFY_SECRET))
+		};
+		let mut conf = ArchifyConf::new(archify_id, archify_secret);
+
+		if !json[CONF_RETENTION].is_null() {
+			conf.retention = serde_json::from_value(json[CONF_RETENTION].clone()).map_err(|e| format!("Invalid {}: {}", CONF_RETENTION, e))?;
+		}
+
+		conf.schedule = json[CONF_SCHEDULE].as_str().map(String::from);
+		if let Some(Err(e)) = conf.schedule.as_deref().map(schedule::Schedule::parse) {
+			return Err(e);
+		}
+
+		if !json[CONF_NOTIFICATIONS].is_null() {
+			conf.notifications = serde_json::from_value(json[CONF_NOTIFICATIONS].clone()).map_err(|e| format!("Invalid {}: {}", CONF_NOTIFICATIONS, e))?;
+		}
+
+		conf.feeds = json[CONF_FEEDS].as_str().map(String::from);
+		conf.metrics_textfile = json[CONF_METRICS_TEXTFILE].as_str().map(String::from);
+		conf.serve_token = json[CONF_SERVE_TOKEN].as_str().map(String::from);
+		Ok(conf)
+	}
+}
+
+#[derive(Serialize)]
+#[non_exhaustive]
+pub struct PlaylistSummary {
+	pub id: String,
+	pub name: Option<String>,
+	pub versions: u64,
+	pub paused: bool,
+	pub next_due: Option<u64>,
+	/// Whether the latest version has an archived cover.
+	pub cover: bool
+}
+
+#[derive(Serialize)]
+#[non_exhaustive]
+pub struct VersionSummary {
+	pub index: u64,
+	pub timestamp: u64,
+	pub name: Option<String>,
+	pub changes: Option<Vec<&'static str>>,
+	pub exported: bool,
+	pub pinned: bool,
+	pub cover: bool
+}
+
+/// Parses a playlist id or an `open.spotify.com/playlist/...` URL.
+///
+/// ```
+/// let id = archify::parse_playlist_id("https://open.spotify.com/playlist/37i9dQZEVXcQ9COmYvdajy?si=1").unwrap();
+/// assert_eq!(archify::parse_playlist_id("37i9dQZEVXcQ9COmYvdajy"), Some(id));
+/// ```
+pub fn parse_playlist_id(playlist_id: &str) -> Option<PlaylistId<'static>>{
+	if PlaylistId::id_is_valid(playlist_id){
+		return PlaylistId::from_id(String::from(playlist_id)).ok();
+	}
+	let url = Url::parse(playlist_id).ok()?;
+	match url.path_segments()?.collect::<Vec<_>>().as_slice() {
+		["playlist", id, ..] => PlaylistId::from_id(String::from(*id)).ok(),
+		_ => None
+	}
+}
+
+/// Resolves a version of a tracked playlist to its index and content.
+pub fn get_version(db: &dyn Storage, playlist_id: &PlaylistId, version: &Version) -> Option<(u64, Playlist)>{
+	let index = db.get_version_index(playlist_id, version)?;
+	db.get_playlist_from_tracked_index(playlist_id, index).map(|p| (index, p))
+}
+
+/// Name of an exported playlist, replacing `{name}`, `{date}`, `{index}` and `{id}` in `template`.
+pub fn format_export_name(template: &str, playlist: &Playlist, index: u64) -> String{
+	let date = DateTime::<Local>::from(UNIX_EPOCH + Duration::from_secs(playlist.timestamp));
+
+	template
+		.replace("{name}", &playlist.data.as_ref().unwrap().name)
+		.replace("{date}", &format!("{}", date.format("%v %X")))
+		.replace("{index}", &index.to_string())
+		.replace("{id}", playlist.id.id())
+}
+
+/// Summaries of `versions`, as returned by `get_all_tracked_versions`.
+pub fn summarize_versions(db: &dyn Storage, playlist_id: &PlaylistId, versions: &[Playlist]) -> Vec<VersionSummary> {
+	let exported = db.get_exported_timestamps(playlist_id);
+	let pinned = db.get_pinned_timestamps(playlist_id);
+	versions.iter()
+		.filter(|p| p.count != 0)
+		.map(|p| VersionSummary {
+			index: p.count,
+			timestamp: p.timestamp,
+			name: p.metadata.as_ref().map(|m| m.name.clone()),
+			changes: p.changes.as_ref().map(|c| c.iter().map(|c| c.as_str()).collect()),
+			exported: exported.contains(&p.timestamp),
+			pinned: pinned.contains(&p.timestamp),
+			cover: p.cover_sha256.is_some()
+		})
+		.collect()
+}
+
+/// Lock held by the process updating the playlists, so that two of them never archive the same version.
+/// `None` if another process holds it. Reading the archive or changing its settings does not need it.
+pub fn lock_updates() -> Option<SingleInstance>{
+	let instance = SingleInstance::new("archify").unwrap();
+	instance.is_single().then_some(instance)
+}
+
+/// Archive of playlists: the database and the Spotify clients, created on first use.
+///
+/// ```no_run
+/// use archify::{Archive, ArchifyConf};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let mut archive = Archive::open(ArchifyConf::new("client id", "client secret")).unwrap();
+///     archive.track("https://open.spotify.com/playlist/37i9dQZEVXcQ9COmYvdajy").await.unwrap();
+///
+///     for event in archive.update(false).await.unwrap() {
+///         println!("{}", event.summary());
+///     }
+///     for playlist in archive.list().await {
+///         println!("{} - {} version(s)", playlist.name.unwrap_or(playlist.id), playlist.versions);
+///     }
+/// }
+/// ```
+pub struct Archive {
+	db: Box<dyn Storage>,
+	conf: ArchifyConf,
+	client: Option<ClientCredsSpotify>,
+	user_client: Option<AuthCodeSpotify>
+}
+
+impl Archive {
+	pub fn new(db: Box<dyn Storage>, conf: ArchifyConf) -> Archive {
+		Archive { db, conf, client: None, user_client: None }
+	}
+
+	/// Archive stored in `data/db.sqlite`. Use `new` with `SqliteStorage::open` to store it elsewhere.
+	pub fn open(conf: ArchifyConf) -> Result<Archive, String> {
+		let db = SqliteStorage::open(CONF_DATABASE_PATH)
+			.map_err(|e| format!("Database {} cannot be opened: {}", CONF_DATABASE_PATH, e))?;
+		Ok(Archive::new(Box::new(db), conf))
+	}
+
+	pub fn db(&self) -> &dyn Storage {
+		self.db.as_ref()
+	}
+
+	pub fn conf(&self) -> &ArchifyConf {
+		&self.conf
+	}
+
+	/// Uses `client`, authorized by the user, to export playlists. Otherwise the token cached by a previous
+	/// authorization is used, and exports fail if there is none: the archive never prompts the user itself.
+	pub fn set_user_client(&mut self, client: AuthCodeSpotify) {
+		self.user_client = Some(client);
+	}
+
+	/// Whether playlists can be exported without prompting the user: a user client was set or a token is cached.
+	pub fn can_export(&self) -> bool {
+		self.user_client.is_some() || spotify::is_user_token_cached()
+	}
+
+	async fn client(&mut self) -> Result<&ClientCredsSpotify, String> {
+		if self.client.is_none(){
+			let client = get_spotify_client_from_client_credentials(&self.conf).await
+				.map_err(|e| format!("Spotify client cannot be authenticated: {}", e))?;
+			self.client = Some(client);
+		}
+		Ok(self.client.as_ref().unwrap())
+	}
+
+	async fn user_client(&mut self) -> Result<&AuthCodeSpotify, String> {
+		if self.user_client.is_none(){
+			self.user_client = spotify::get_cached_user_client(&self.conf).await
+				.map_err(|e| format!("Spotify user token cannot be read: {}", e))?;
+		}
+		self.user_client.as_ref().ok_or_else(|| String::from("No Spotify user token cached, export a playlist with the CLI once to authorize archify."))
+	}
+
+	/// Starts tracking a playlist, given by id or URL, or restores it from the trash. Fails if it cannot be parsed.
+	///
+	/// ```no_run
+	/// # async fn example(archive: &archify::Archive) {
+	/// let id = archive.track("37i9dQZEVXcQ9COmYvdajy").await.expect("invalid playlist");
+	/// # }
+	/// ```
+	pub async fn track(&self, playlist: &str) -> Result<PlaylistId<'static>, String> {
+		let playlist_id = parse_playlist_id(playlist).ok_or_else(|| format!("{} is not a valid playlist URL or ID.", playlist))?;
+		self.db.set_unique_empty_playlist(&playlist_id);
+		Ok(playlist_id)
+	}
+
+	/// Stops tracking a playlist by moving it to the trash, or deletes it with its whole history if `hard`. Fails if it
+	/// cannot be parsed.
+	///
+	/// ```no_run
+	/// # async fn example(archive: &archify::Archive) {
+	/// archive.untrack("37i9dQZEVXcQ9COmYvdajy", false).await.unwrap();
+	/// # }
+	/// ```
+	pub async fn untrack(&self, playlist: &str, hard: bool) -> Result<PlaylistId<'static>, String> {
+		let playlist_id = parse_playlist_id(playlist).ok_or_else(|| format!("{} is not a valid playlist URL or ID.", playlist))?;
+		if hard {
+			self.db.delete_playlist(&playlist_id);
+		}else{
+			let now = SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.unwrap()
+				.as_secs();
+			self.db.trash_playlist(&playlist_id, now);
+		}
+		Ok(playlist_id)
+	}
+
+	/// Tracked playlists, with their number of versions and next update.
+	///
+	/// ```no_run
+	/// # async fn example(archive: &archify::Archive) {
+	/// for playlist in archive.list().await.iter().filter(|p| !p.paused) {
+	///     println!("{}: next update at {:?}", playlist.id, playlist.next_due);
+	/// }
+	/// # }
+	/// ```
+	pub async fn list(&self) -> Vec<PlaylistSummary> {
+		self.db.get_latest_unique_playlists()
+			.into_iter()
+			.map(|p| PlaylistSummary {
+				id: p.id.id().to_string(),
+				name: p.metadata.map(|m| m.name),
+				versions: p.count - 1,
+				paused: self.db.is_paused(&p.id),
+				next_due: self.next_due(&p.id),
+				cover: p.cover_sha256.is_some()
+			})
+			.collect()
+	}
+
+	/// Versions of a playlist ascending by timestamp, empty if none was recorded.
+	///
+	/// ```no_run
+	/// # async fn example(archive: &archify::Archive) {
+	/// let id = archify::parse_playlist_id("37i9dQZEVXcQ9COmYvdajy").unwrap();
+	/// for version in archive.versions(&id).await {
+	///     println!("{} - {} - {:?}", version.index, version.timestamp, version.changes);
+	/// }
+	/// # }
+	/// ```
+	pub async fn versions(&self, playlist_id: &PlaylistId<'_>) -> Vec<VersionSummary> {
+		summarize_versions(self.db(), playlist_id, &self.db.get_all_tracked_versions(playlist_id))
+	}
+
+	/// Index and content of a version of a playlist.
+	///
+	/// ```no_run
+	/// # async fn example(archive: &archify::Archive) {
+	/// use archify::Version;
+	///
+	/// let id = archify::parse_playlist_id("37i9dQZEVXcQ9COmYvdajy").unwrap();
+	/// if let Some((index, playlist)) = archive.version(&id, &Version::Latest).await {
+	///     println!("Version {} has {} items", index, playlist.data.as_ref().unwrap().tracks.total);
+	/// }
+	/// # }
+	/// ```
+	pub async fn version(&self, playlist_id: &PlaylistId<'_>, version: &Version) -> Option<(u64, Playlist)> {
+		get_version(self.db(), playlist_id, version)
+	}
+
+	/// Schedule of a playlist, falling back to the global one. `None` if neither is configured, or if the one stored
+	/// is invalid.
+	pub fn schedule(&self, playlist_id: &PlaylistId) -> Option<String>{
+		self.parsed_schedule(playlist_id).map(|(spec, _)| spec)
+	}
+
+	fn parsed_schedule(&self, playlist_id: &PlaylistId) -> Option<(String, schedule::Schedule)>{
+		let spec = self.db.get_schedule(playlist_id).or_else(|| self.conf.schedule.clone())?;
+		match schedule::Schedule::parse(&spec) {
+			Ok(schedule) => Some((spec, schedule)),
+			Err(e) => {
+				error!("Schedule of playlist {} ignored: {}", playlist_id.id(), e);
+				None
+			}
+		}
+	}
+
+	/// Time at which a playlist is due for an update. `None` if it is paused or its schedule never triggers again.
+	/// A playlist without a schedule is due from its last check on, so that every update includes it.
+	pub fn next_due(&self, playlist_id: &PlaylistId) -> Option<u64>{
+		if self.db.is_paused(playlist_id){
+			return None;
+		}
+		let last_check = self.db.get_last_check(playlist_id);
+		match self.parsed_schedule(playlist_id) {
+			Some((_, schedule)) => Some(schedule.next_after(last_check)).filter(|d| *d != u64::MAX),
+			None => Some(last_check)
+		}
+	}
+
+	/// Latest versions of the playlists due for an update, or of every playlist if `force`.
+	pub fn due_playlists(&self, force: bool) -> Vec<Playlist>{
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap()
+			.as_secs();
+
+		self.db.get_latest_unique_playlists()
+			.into_iter()
+			.filter(|p| {
+				let due = force || self.next_due(&p.id).is_some_and(|d| d <= now + CONF_SCHEDULE_TOLERANCE);
+				if !due {
+					info!("Playlist {} is not due, skipped.", p.id.id());
+				}
+				due
+			})
+			.collect()
+	}
+
+	/// Updates the due playlists, or every playlist if `force`, then notifies what happened. Fails if Spotify cannot be
+	/// reached at all; a playlist that cannot be updated is reported by an `Event::UpdateFailed` instead.
+	///
+	/// ```no_run
+	/// # async fn example(archive: &mut archify::Archive) {
+	/// use archify::Event;
+	///
+	/// for event in archive.update(true).await.unwrap() {
+	///     if let Event::NewVersion { playlist_name, added, removed, .. } = event {
+	///         println!("{}: {} added, {} removed", playlist_name, added, removed);
+	///     }
+	/// }
+	/// # }
+	/// ```
+	pub async fn update(&mut self, force: bool) -> Result<Vec<notify::Event>, String> {
+		let playlists = self.due_playlists(force);
+
+		if playlists.is_empty(){
+			info!("No playlist is due.");
+			return Ok(Vec::new());
+		}
+
+		let run = metrics::start_run();
+		let mut events = Vec::new();
+		let mut res = Ok(());
+		for p in playlists{
+			match self.update_playlist(&p).await {
+				Ok(event) => events.extend(event),
+				Err(e) => {
+					// Every playlist would fail the same way, the first one is notified
+					events.push(self.update_failed(&p, &e));
+					res = Err(e);
+					break;
+				}
+			}
+		}
+		self.finish_updates(&events, run).await;
+		res.map(|_| events)
+	}
+
+	/// Event notifying that `p` cannot be updated because of `error`, counted as a failure.
+	pub(crate) fn update_failed(&self, p: &Playlist, error: &str) -> notify::Event {
+		let timestamp = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap()
+			.as_secs();
+		metrics::inc_failures(p.id.id());
+		notify::Event::UpdateFailed {
+			playlist_id: p.id.id().to_string(),
+			playlist_name: self.db.get_playlist_name(&p.id),
+			timestamp,
+			error: error.to_string()
+		}
+	}
+
+	/// Fetches a playlist and stores it as a new version if it changed since `p`, its latest version.
+	/// Returns the event to notify, if any. Fails only if Spotify cannot be reached at all.
+	pub async fn update_playlist(&mut self, p: &Playlist) -> Result<Option<notify::Event>, String>{
+		let client = self.client().await?;
+
+		let mut fresh_p = match spotify::get_public_playlists(client, &p.id).await {
+			Ok(fresh_p) => fresh_p,
+			Err(e) => {
+				let timestamp = SystemTime::now()
+					.duration_since(UNIX_EPOCH)
+					.unwrap()
+					.as_secs();
+				let playlist_id = p.id.id().to_string();
+				let playlist_name = self.db.get_playlist_name(&p.id);
+				metrics::inc_failures(&playlist_id);
+				if spotify::is_not_found(&e){
+					// The playlist is paused so that the deletion is notified once, not at every update
+					if self.db.is_paused(&p.id){
+						error!("Playlist {} not found on Spotify anymore, already paused.", p.id.id());
+						return Ok(None);
+					}
+					error!("Playlist {} not found on Spotify anymore, paused.", p.id.id());
+					self.db.set_paused(&p.id, true);
+					return Ok(Some(notify::Event::DeletedUpstream { playlist_id, playlist_name, timestamp }));
+				}
+				error!("Playlist {} cannot be updated: {}", p.id.id(), e);
+				return Ok(Some(notify::Event::UpdateFailed { playlist_id, playlist_name, timestamp, error: e.to_string() }));
+			}
+		};
+
+		let data = fresh_p.data.as_ref().unwrap();
+		self.db.set_observation(&database::Observation {
+			id: fresh_p.id.clone_static(),
+			timestamp: fresh_p.timestamp,
+			followers: data.followers.total,
+			snapshot_id: data.snapshot_id.clone(),
+			item_count: data.tracks.total
+		});
+
+		if fresh_p.is_new_version_of(p){
+			fresh_p.changes = Some(fresh_p.changes_since(p));
+			self.db.set_playlist(&fresh_p);
+			metrics::inc_versions_inserted();
+
+			if let Some(template) = self.db.get_auto_export(&p.id){
+				if let Err(e) = self.export_version(&fresh_p, p.count, &template).await {
+					error!("Playlist {} cannot be exported: {}", p.id.id(), e);
+				}
+			}
+
+			let old_items = p.data.as_ref().map(items::get_items).unwrap_or_default();
+			let d = diff::diff(&old_items, &items::get_items(fresh_p.data.as_ref().unwrap()));
+			Ok(Some(notify::Event::NewVersion {
+				playlist_id: fresh_p.id.id().to_string(),
+				playlist_name: fresh_p.data.as_ref().unwrap().name.clone(),
+				timestamp: fresh_p.timestamp,
+				index: p.count,
+				changes: fresh_p.changes.as_ref().unwrap().iter().map(|c| String::from(c.as_str())).collect(),
+				added: d.added.len(),
+				removed: d.removed.len()
+			}))
+		}else{
+			info!("Playlist {} SHA matching, not pushed to db.", p.id.id());
+			Ok(None)
+		}
+	}
+
+	/// Notifies the events of a run of updates, then writes the feeds and metrics if configured.
+	pub(crate) async fn finish_updates(&self, events: &[notify::Event], run: metrics::Run){
+		metrics::record_update(run);
+		notify::send(&self.conf.notifications, events).await;
+		if let Some(dir) = &self.conf.feeds {
+			feed::update(self.db(), dir, events);
+		}
+		if let Some(path) = &self.conf.metrics_textfile {
+			metrics::write_textfile(self.db(), path);
+		}
+	}
+
+	/// Recreates a version of a playlist in the account of the user, named after `template` (see `format_export_name`).
+	/// Fails if there is no such version or no user client (see `set_user_client`). An export rejected by Spotify is
+	/// recorded and returned with its error.
+	///
+	/// ```no_run
+	/// # async fn example(archive: &mut archify::Archive) {
+	/// use archify::Version;
+	///
+	/// let id = archify::parse_playlist_id("37i9dQZEVXcQ9COmYvdajy").unwrap();
+	/// match archive.export(&id, &Version::First, "{name} - first version").await {
+	///     Ok(result) => println!("Exported to {:?}", result.exported_id),
+	///     Err(e) => println!("{}", e)
+	/// }
+	/// # }
+	/// ```
+	pub async fn export(&mut self, playlist_id: &PlaylistId<'_>, version: &Version, template: &str) -> Result<ExportResult, String> {
+		let (index, playlist) = get_version(self.db(), playlist_id, version)
+			.ok_or_else(|| String::from("No playlist with this id & index are stored. Check --tracked."))?;
+		self.export_version(&playlist, index, template).await
+	}
+
+	/// Exports `playlist`, version `index`, and records the result.
+	pub async fn export_version(&mut self, playlist: &Playlist, index: u64, template: &str) -> Result<ExportResult, String>{
+		let name = format_export_name(template, playlist, index);
+		let res = spotify::export_playlist_to_user(self.user_client().await?, playlist, &name).await;
+
+		let export_timestamp = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap()
+			.as_secs();
+
+		let (exported_id, error) = match res {
+			Ok(id) => (Some(id.id().to_string()), None),
+			Err(e) => {
+				error!("Export of playlist {} failed: {}", playlist.id.id(), e);
+				(None, Some(e.to_string()))
+			}
+		};
+
+		let result = ExportResult {
+			id: playlist.id.clone_static(),
+			timestamp: playlist.timestamp,
+			export_timestamp,
+			exported_id,
+			error
+		};
+		self.db.set_export_result(&result);
+		Ok(result)
+	}
+}
@@ -1,4 +1,10 @@
-use clap::{Parser, error::ErrorKind, CommandFactory};
+use clap::{Parser, Subcommand, error::ErrorKind, CommandFactory};
+
+use archify::conf::*;
+use archify::database::Version;
+use archify::report::{ReportFormat, SeriesFormat};
+use archify::retention::RetentionPolicy;
+use archify::schedule;
 
 #[derive(Parser)]
 #[clap(author="Inheritor-Vision")]
@@ -9,35 +15,338 @@ struct Cli {
 	/// Add public playlist to archive
 	#[arg(short, long, value_parser, num_args(1..))]
 	add_playlist: Option<Vec<String>>,
-	/// Update playlists stored in database
+	/// Update playlists stored in database which are due according to their schedule
 	#[arg(short,long,action,value_parser)]
 	update: bool,
+	/// With --update, update every playlist, even paused or not due ones
+	#[arg(long, action, requires("update"))]
+	force: bool,
 	/// List tracked playlists
 	#[arg(short,long,action,value_parser)]
 	list: bool,
 	/// List versions of a single tracked playlist
 	#[arg(short,long,action,value_parser)]
 	tracked: Option<String>,
-	/// Based on playlist id and index given by --tracked, export the playlist to your spotify
+	/// Based on playlist id and version (index given by --tracked, first, latest or @DATE), export the playlist to your spotify
 	#[arg(short, long, value_parser, num_args(2..3))]
 	export: Option<Vec<String>>,
-	/// Delete a playlist
+	/// Delete a playlist, moving it to the trash
 	#[arg(short,long,value_parser, num_args(1..))] 
 	delete_playlist: Option<Vec<String>>,
+	/// With --delete-playlist, delete the playlist and its whole history immediately instead of moving it to the trash
+	#[arg(long, action, requires("delete_playlist"))]
+	hard: bool,
+	/// Enable (on) or disable (off) the export of every new version of a playlist during --update, with an optional name template ({name}, {date}, {index}, {id})
+	#[arg(long, value_parser, num_args(2..4), value_names(["PLAYLIST", "on|off", "TEMPLATE"]))]
+	auto_export: Option<Vec<String>>,
+	/// Based on playlist id and version (index given by --tracked, first, latest or @DATE), write the archived cover of the version to a file
+	#[arg(long, value_parser, num_args(3), value_names(["PLAYLIST", "VERSION", "FILE"]))]
+	extract_cover: Option<Vec<String>>,
+	/// Based on playlist id and version (index given by --tracked, first, latest or @DATE), list the items of the version
+	#[arg(short, long, value_parser, num_args(2), value_names(["PLAYLIST", "VERSION"]))]
+	show: Option<Vec<String>>,
+	/// Based on playlist id and versions (indexes given by --tracked, first, latest or @DATE), list the items added and removed between two versions
+	#[arg(long, value_parser, num_args(3), value_names(["PLAYLIST", "FROM", "TO"]))]
+	diff: Option<Vec<String>>,
+	/// Based on playlist id and version (index given by --tracked, first, latest or @DATE), write the items of the version to a CSV file (or JSON if FILE ends with .json)
+	#[arg(long, value_parser, num_args(3), value_names(["PLAYLIST", "VERSION", "FILE"]))]
+	export_file: Option<Vec<String>>,
+	#[command(subcommand)]
+	command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+	/// Statistics computed from the archive
+	#[command(args_conflicts_with_subcommands = true)]
+	Stats {
+		/// Playlist whose versions are summarized: distinct tracks and artists, repeats, churn, durations and release years
+		playlist: Option<String>,
+		#[arg(short, long, value_enum, default_value_t = ReportFormat::Text)]
+		format: ReportFormat,
+		#[command(subcommand)]
+		kind: Option<StatsKind>
+	},
+	/// Items added and removed by each user across the versions of a tracked playlist
+	Contributors {
+		playlist: String
+	},
+	/// Time ranges and positions in which a track appeared, across every tracked playlist
+	TrackHistory {
+		/// Track URI, URL or ID
+		track: String,
+		#[arg(short, long, value_enum, default_value_t = ReportFormat::Text)]
+		format: ReportFormat
+	},
+	/// Overlap and similarity of two or more tracked playlists
+	Compare {
+		/// PLAYLIST, PLAYLIST:INDEX (as given by --tracked), PLAYLIST:first, PLAYLIST:latest or PLAYLIST@DATE (YYYY-MM-DD or RFC 3339), the latest version being used by default
+		#[arg(num_args(2..), required = true)]
+		playlists: Vec<String>,
+		#[arg(short, long, value_enum, default_value_t = ReportFormat::Text)]
+		format: ReportFormat
+	},
+	/// Set the retention policy of a tracked playlist, overriding the global one of config.json, or show it when no rule is given
+	Retention {
+		playlist: String,
+		/// Keep the last N versions
+		#[arg(long, value_name = "N")]
+		keep_last: Option<u64>,
+		/// Keep versions younger than D days
+		#[arg(long, value_name = "D")]
+		keep_days: Option<u64>,
+		/// Keep the latest version of each of the last N days
+		#[arg(long, value_name = "N")]
+		keep_daily: Option<u64>,
+		/// Keep the latest version of each of the last N weeks
+		#[arg(long, value_name = "N")]
+		keep_weekly: Option<u64>,
+		/// Keep the latest version of each of the last N months
+		#[arg(long, value_name = "N")]
+		keep_monthly: Option<u64>,
+		/// Remove the policy of the playlist, the global one applying again
+		#[arg(long, action, conflicts_with_all(["keep_last", "keep_days", "keep_daily", "keep_weekly", "keep_monthly"]))]
+		clear: bool
+	},
+	/// Protect a version (index given by --tracked, first, latest or @DATE) from pruning
+	Pin {
+		playlist: String,
+		version: String
+	},
+	/// Allow a pinned version to be pruned again
+	Unpin {
+		playlist: String,
+		version: String
+	},
+	/// Delete the versions not kept by the retention policies, for the given playlists or every tracked one
+	Prune {
+		playlists: Vec<String>,
+		/// Only show the versions that would be deleted
+		#[arg(long, action)]
+		dry_run: bool
+	},
+	/// Set the update schedule of a tracked playlist, overriding the global one of config.json, or show it when no schedule is given
+	Schedule {
+		playlist: String,
+		/// Preset (discover-weekly, release-radar, daily), interval (30m, 6h, 1d, 1w) or cron expression (sec min hour day-of-month month day-of-week)
+		#[arg(conflicts_with("clear"))]
+		schedule: Option<String>,
+		/// Remove the schedule of the playlist, the global one applying again. Without any, the playlist is updated at every --update
+		#[arg(long, action)]
+		clear: bool
+	},
+	/// Serve the archive over a local HTTP JSON API and web UI
+	Serve {
+		/// Address to listen on. Every request must carry the serve_token of config.json, or of the
+		/// ARCHIFY_SERVE_TOKEN environment variable, if any
+		#[arg(long, default_value = CONF_SERVE_ADDRESS)]
+		address: String
+	},
+	/// Send a sample event to every notification sink of config.json
+	TestNotifications,
+	/// Stop updating tracked playlists, keeping their versions
+	Pause {
+		#[arg(required = true)]
+		playlists: Vec<String>
+	},
+	/// Update paused playlists again
+	Resume {
+		#[arg(required = true)]
+		playlists: Vec<String>
+	},
+	/// Keep running and update every tracked playlist according to its schedule, until SIGTERM or Ctrl-C
+	Daemon,
+	/// Playlists removed with --delete-playlist
+	Trash {
+		#[command(subcommand)]
+		action: TrashAction
+	},
+	/// Static HTML site of the archive
+	Site {
+		#[command(subcommand)]
+		action: SiteAction
+	},
+	/// Full-text search of track titles, artists, albums and playlist names in the archive
+	Search {
+		/// FTS5 query, e.g. 'daft punk' or 'name:intro*'
+		query: Option<String>,
+		/// Rebuild the search index from the archived versions
+		#[arg(long, action)]
+		rebuild: bool,
+		#[arg(short, long, value_enum, default_value_t = ReportFormat::Text)]
+		format: ReportFormat
+	},
+}
+
+#[derive(Subcommand)]
+enum TrashAction {
+	/// List the trashed playlists
+	List,
+	/// Track trashed playlists again
+	Restore {
+		#[arg(required = true)]
+		playlists: Vec<String>
+	},
+	/// Delete trashed playlists and their whole history
+	Purge {
+		/// Only purge playlists trashed more than DAYS days ago
+		#[arg(long, value_name = "DAYS")]
+		older_than: Option<u64>
+	},
+}
+
+#[derive(Subcommand)]
+enum SiteAction {
+	/// Render every tracked playlist, its versions and diffs into DIR, only rewriting the pages that changed
+	Build {
+		#[arg(value_name = "DIR")]
+		path: String
+	},
+}
+
+#[derive(Subcommand)]
+enum StatsKind {
+	/// Follower count observed at each --update of a tracked playlist
+	Followers {
+		playlist: String,
+		#[arg(short, long, value_enum, default_value_t = SeriesFormat::Table)]
+		format: SeriesFormat
+	},
 }
 
 pub struct ExportArgs{
 	pub playlist_id: String,
-	pub index: u64
+	pub version: Version
+}
+
+pub struct AutoExportArgs{
+	pub playlist_id: String,
+	pub enabled: bool,
+	pub template: Option<String>
+}
+
+pub struct VersionFileArgs{
+	pub playlist_id: String,
+	pub version: Version,
+	pub path: String
+}
+
+pub struct DiffArgs{
+	pub playlist_id: String,
+	pub from: Version,
+	pub to: Version
+}
+
+pub struct FollowersArgs{
+	pub playlist_id: String,
+	pub format: SeriesFormat
+}
+
+pub struct StatsArgs{
+	pub playlist_id: String,
+	pub format: ReportFormat
+}
+
+pub struct TrackHistoryArgs{
+	pub track: String,
+	pub format: ReportFormat
+}
+
+pub struct CompareArgs{
+	pub playlists: Vec<(String, Version)>,
+	pub format: ReportFormat
+}
+
+pub struct RetentionArgs{
+	pub playlist_id: String,
+	/// `None` to show the current policy.
+	pub policy: Option<RetentionPolicy>,
+	pub clear: bool
+}
+
+pub struct PinArgs{
+	pub playlist_id: String,
+	pub version: Version,
+	pub pinned: bool
+}
+
+pub struct PruneArgs{
+	pub playlist_ids: Vec<String>,
+	pub dry_run: bool
+}
+
+pub struct DeleteArgs{
+	pub playlist_ids: Vec<String>,
+	pub hard: bool
+}
+
+pub struct ScheduleArgs{
+	pub playlist_id: String,
+	pub schedule: Option<String>,
+	pub clear: bool
+}
+
+pub struct ServeArgs{
+	pub address: String
+}
+
+pub struct SearchArgs{
+	pub query: Option<String>,
+	pub rebuild: bool,
+	pub format: ReportFormat
 }
 
 pub enum Args {
 	NewPlaylist(Vec<String>),
-	DeletePlaylist(Vec<String>),
-	Update,
+	DeletePlaylist(DeleteArgs),
+	Update(bool),
 	List,
 	Tracked(String),
-	Export(ExportArgs)
+	Export(ExportArgs),
+	AutoExport(AutoExportArgs),
+	ExtractCover(VersionFileArgs),
+	Followers(FollowersArgs),
+	Stats(StatsArgs),
+	Show(ExportArgs),
+	Diff(DiffArgs),
+	ExportFile(VersionFileArgs),
+	Contributors(String),
+	TrackHistory(TrackHistoryArgs),
+	Compare(CompareArgs),
+	Retention(RetentionArgs),
+	Pin(PinArgs),
+	Prune(PruneArgs),
+	Schedule(ScheduleArgs),
+	Daemon,
+	TestNotifications,
+	Serve(ServeArgs),
+	Pause(Vec<String>, bool),
+	TrashList,
+	TrashRestore(Vec<String>),
+	TrashPurge(Option<u64>),
+	SiteBuild(String),
+	Search(SearchArgs)
+}
+
+/// Parses `YYYY-MM-DD`, standing for the end of that day in local time, or an RFC 3339 date.
+fn parse_version(version: &str) -> Version{
+	match Version::parse(version) {
+		Some(version) => version,
+		None => Cli::command().error(ErrorKind::InvalidValue, format!("{} is not a valid version.", version)).exit()
+	}
+}
+
+/// Splits `PLAYLIST[:INDEX|:first|:latest|@DATE]`.
+fn parse_playlist_version(spec: &str) -> (String, Version){
+	if let Some((playlist, date)) = spec.rsplit_once('@') {
+		return (String::from(playlist), parse_version(&format!("@{}", date)));
+	}
+	match spec.rsplit_once(':') {
+		Some((playlist, version)) if version == "first" || version == "latest" || version.parse::<u64>().is_ok() => {
+			(String::from(playlist), parse_version(version))
+		},
+		_ => (String::from(spec), Version::Latest)
+	}
 }
 
 pub fn parse_args() -> Args{
@@ -45,11 +354,11 @@ pub fn parse_args() -> Args{
 	let res;
 
 	if cli.update {
-		res = Args::Update;
+		res = Args::Update(cli.force);
 	}else if cli.add_playlist != None {
 		res = Args::NewPlaylist(cli.add_playlist.unwrap());
 	}else if cli.delete_playlist != None {
-		res = Args::DeletePlaylist(cli.delete_playlist.unwrap());
+		res = Args::DeletePlaylist(DeleteArgs { playlist_ids: cli.delete_playlist.unwrap(), hard: cli.hard });
 	}else if cli.list {
 		res = Args::List;
 	} else if cli.tracked != None {
@@ -58,10 +367,114 @@ pub fn parse_args() -> Args{
 		res = Args::Export(
 			ExportArgs { 
 				playlist_id: cli.export.as_ref().unwrap().get(0).unwrap().clone(),
-				index:  cli.export.as_ref().unwrap().get(1).unwrap().clone().parse().unwrap(),
+				version: parse_version(cli.export.as_ref().unwrap().get(1).unwrap()),
 			}
 		);
 	
+	}else if let Some(auto_export) = cli.auto_export {
+		let enabled = match auto_export[1].as_str() {
+			"on" => true,
+			"off" => false,
+			_ => Cli::command().error(ErrorKind::InvalidValue, "Auto-export must be set to 'on' or 'off'.").exit()
+		};
+		res = Args::AutoExport(
+			AutoExportArgs {
+				playlist_id: auto_export[0].clone(),
+				enabled,
+				template: auto_export.get(2).cloned()
+			}
+		);
+	}else if let Some(extract_cover) = cli.extract_cover {
+		res = Args::ExtractCover(
+			VersionFileArgs {
+				playlist_id: extract_cover[0].clone(),
+				version: parse_version(&extract_cover[1]),
+				path: extract_cover[2].clone()
+			}
+		);
+	}else if let Some(show) = cli.show {
+		res = Args::Show(
+			ExportArgs {
+				playlist_id: show[0].clone(),
+				version: parse_version(&show[1])
+			}
+		);
+	}else if let Some(diff) = cli.diff {
+		res = Args::Diff(
+			DiffArgs {
+				playlist_id: diff[0].clone(),
+				from: parse_version(&diff[1]),
+				to: parse_version(&diff[2])
+			}
+		);
+	}else if let Some(export_file) = cli.export_file {
+		res = Args::ExportFile(
+			VersionFileArgs {
+				playlist_id: export_file[0].clone(),
+				version: parse_version(&export_file[1]),
+				path: export_file[2].clone()
+			}
+		);
+	}else if let Some(command) = cli.command {
+		res = match command {
+			Command::Stats { kind: Some(StatsKind::Followers { playlist, format }), .. } => Args::Followers(
+				FollowersArgs {
+					playlist_id: playlist,
+					format
+				}
+			),
+			Command::Stats { playlist: Some(playlist), format, kind: None } => Args::Stats(
+				StatsArgs {
+					playlist_id: playlist,
+					format
+				}
+			),
+			Command::Stats { playlist: None, kind: None, .. } => {
+				Cli::command().error(ErrorKind::MissingRequiredArgument, "A playlist or a statistics kind is required.").exit()
+			},
+			Command::Contributors { playlist } => Args::Contributors(playlist),
+			Command::TrackHistory { track, format } => Args::TrackHistory(TrackHistoryArgs { track, format }),
+			Command::Compare { playlists, format } => Args::Compare(
+				CompareArgs {
+					playlists: playlists.iter().map(|p| parse_playlist_version(p)).collect(),
+					format
+				}
+			),
+			Command::Retention { playlist, keep_last, keep_days, keep_daily, keep_weekly, keep_monthly, clear } => {
+				let policy = RetentionPolicy { keep_last, keep_days, keep_daily, keep_weekly, keep_monthly };
+				Args::Retention(
+					RetentionArgs {
+						playlist_id: playlist,
+						policy: Some(policy).filter(|p| !p.is_empty()),
+						clear
+					}
+				)
+			},
+			Command::Pin { playlist, version } => Args::Pin(PinArgs { playlist_id: playlist, version: parse_version(&version), pinned: true }),
+			Command::Unpin { playlist, version } => Args::Pin(PinArgs { playlist_id: playlist, version: parse_version(&version), pinned: false }),
+			Command::Prune { playlists, dry_run } => Args::Prune(PruneArgs { playlist_ids: playlists, dry_run }),
+			Command::Schedule { playlist, schedule, clear } => {
+				if let Some(Err(e)) = schedule.as_deref().map(schedule::Schedule::parse) {
+					Cli::command().error(ErrorKind::InvalidValue, e).exit()
+				}
+				Args::Schedule(ScheduleArgs { playlist_id: playlist, schedule, clear })
+			},
+			Command::Daemon => Args::Daemon,
+			Command::TestNotifications => Args::TestNotifications,
+			Command::Serve { address } => Args::Serve(ServeArgs { address }),
+			Command::Pause { playlists } => Args::Pause(playlists, true),
+			Command::Resume { playlists } => Args::Pause(playlists, false),
+			Command::Trash { action: TrashAction::List } => Args::TrashList,
+			Command::Trash { action: TrashAction::Restore { playlists } } => Args::TrashRestore(playlists),
+			Command::Trash { action: TrashAction::Purge { older_than } } => Args::TrashPurge(older_than),
+			Command::Site { action: SiteAction::Build { path } } => Args::SiteBuild(path),
+			Command::Search { query, rebuild, format } => {
+				if query.is_none() && !rebuild {
+					Cli::command().error(ErrorKind::MissingRequiredArgument, "A query or --rebuild is required.").exit()
+				}
+				Args::Search(SearchArgs { query, rebuild, format })
+			},
+		};
 	}else{
 		let mut cmd = Cli::command();
 		cmd.error(
@@ -0,0 +1,148 @@
+use crate::report::ReportFormat;
+use crate::items::Item;
+
+use std::collections::HashSet;
+use std::hash::Hash;
+
+use serde::Serialize;
+
+/// Version of a playlist taking part in a comparison.
+#[derive(Serialize)]
+pub struct ComparedVersion {
+	pub playlist_id: String,
+	pub playlist_name: Option<String>,
+	pub index: u64,
+	pub timestamp: u64,
+	pub tracks: usize,
+	pub artists: usize
+}
+
+impl ComparedVersion {
+	pub fn new(playlist_id: String, playlist_name: Option<String>, index: u64, timestamp: u64, items: &[Item]) -> ComparedVersion {
+		ComparedVersion {
+			playlist_id,
+			playlist_name,
+			index,
+			timestamp,
+			tracks: items.iter().map(|i| i.uri.as_str()).collect::<HashSet<_>>().len(),
+			artists: items.iter().flat_map(|i| i.artists.iter()).collect::<HashSet<_>>().len()
+		}
+	}
+}
+
+#[derive(Serialize)]
+pub struct Comparison {
+	pub versions: Vec<ComparedVersion>,
+	/// Items present in every compared version.
+	pub shared_tracks: Vec<Item>,
+	/// Artists present in every compared version.
+	pub shared_artists: Vec<String>,
+	/// Jaccard similarity of the items of every compared version.
+	pub jaccard: f64,
+	/// Pairwise Jaccard similarity of the items, in the order of `versions`.
+	pub matrix: Vec<Vec<f64>>,
+	/// Pairwise Jaccard similarity of the artists, in the order of `versions`.
+	pub artist_matrix: Vec<Vec<f64>>
+}
+
+fn jaccard<T: Eq + Hash>(sets: &[&HashSet<T>]) -> f64 {
+	let union: HashSet<&T> = sets.iter().flat_map(|s| s.iter()).collect();
+	if union.is_empty() {
+		return 0.0;
+	}
+	let intersection = union.iter().filter(|e| sets.iter().all(|s| s.contains(**e))).count();
+	intersection as f64 / union.len() as f64
+}
+
+fn matrix<T: Eq + Hash>(sets: &[HashSet<T>]) -> Vec<Vec<f64>> {
+	sets.iter()
+		.map(|a| sets.iter().map(|b| jaccard(&[a, b])).collect())
+		.collect()
+}
+
+/// Compares the items of several versions, given with their description.
+pub fn compare(versions: Vec<(ComparedVersion, Vec<Item>)>) -> Comparison {
+	let tracks: Vec<HashSet<&str>> = versions.iter()
+		.map(|(_, items)| items.iter().map(|i| i.uri.as_str()).collect())
+		.collect();
+	let artists: Vec<HashSet<&str>> = versions.iter()
+		.map(|(_, items)| items.iter().flat_map(|i| i.artists.iter().map(|a| a.as_str())).collect())
+		.collect();
+
+	let mut shared_tracks: Vec<Item> = Vec::new();
+	for item in &versions[0].1 {
+		if tracks.iter().all(|t| t.contains(item.uri.as_str())) && !shared_tracks.iter().any(|s| s.uri == item.uri) {
+			shared_tracks.push(item.clone());
+		}
+	}
+	let mut shared_artists: Vec<String> = artists[0].iter()
+		.filter(|a| artists.iter().all(|s| s.contains(*a)))
+		.map(|a| String::from(*a))
+		.collect();
+	shared_artists.sort();
+
+	let jaccard = jaccard(&tracks.iter().collect::<Vec<_>>());
+	let matrix_tracks = matrix(&tracks);
+	let matrix_artists = matrix(&artists);
+
+	Comparison {
+		versions: versions.into_iter().map(|(v, _)| v).collect(),
+		shared_tracks,
+		shared_artists,
+		jaccard,
+		matrix: matrix_tracks,
+		artist_matrix: matrix_artists
+	}
+}
+
+fn print_matrix(versions: &[ComparedVersion], matrix: &[Vec<f64>]){
+	print!("{: >4}", "");
+	for i in 1..=versions.len() {
+		print!(" {: >6}", format!("#{}", i));
+	}
+	println!();
+	for (i, row) in matrix.iter().enumerate() {
+		print!("{: >4}", format!("#{}", i + 1));
+		for value in row {
+			print!(" {: >6.3}", value);
+		}
+		println!();
+	}
+}
+
+pub fn print_comparison(comparison: &Comparison, format: ReportFormat){
+	match format {
+		ReportFormat::Json => println!("{}", serde_json::to_string_pretty(comparison).unwrap()),
+		ReportFormat::Text => {
+			for (i, v) in comparison.versions.iter().enumerate() {
+				println!(
+					"#{}: [{}] - {} - version {}: {} tracks, {} artists",
+					i + 1, v.playlist_id, v.playlist_name.as_deref().unwrap_or_default(), v.index, v.tracks, v.artists
+				);
+			}
+
+			println!();
+			println!("Jaccard similarity of all versions: {:.3}", comparison.jaccard);
+
+			println!();
+			println!("Shared tracks ({}):", comparison.shared_tracks.len());
+			for item in &comparison.shared_tracks {
+				println!("  {}{} - {} <{}>", item.marker(), item.name, item.creator(), item.uri);
+			}
+
+			println!();
+			println!("Shared artists ({}):", comparison.shared_artists.len());
+			for artist in &comparison.shared_artists {
+				println!("  {}", artist);
+			}
+
+			println!();
+			println!("Track similarity:");
+			print_matrix(&comparison.versions, &comparison.matrix);
+
+			println!();
+			println!("Artist similarity:");
+			print_matrix(&comparison.versions, &comparison.artist_matrix);
+		}
+	}
+}
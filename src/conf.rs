@@ -4,6 +4,12 @@ use rspotify::model::FullPlaylist;
 // Dynamic Configuration Fields
 pub const CONF_ARCHIFY_ID: &str								= "archify_id";
 pub const CONF_ARCHIFY_SECRET: &str							= "archify_secret";
+pub const CONF_RETENTION: &str								= "retention";
+pub const CONF_SCHEDULE: &str								= "schedule";
+pub const CONF_NOTIFICATIONS: &str							= "notifications";
+pub const CONF_FEEDS: &str									= "feeds";
+pub const CONF_METRICS_TEXTFILE: &str						= "metrics_textfile";
+pub const CONF_SERVE_TOKEN: &str							= "serve_token";
 
 // Paths
 pub const CONF_DATABASE_PATH: &str							= "data/db.sqlite";
@@ -16,6 +22,50 @@ pub const RSPOTIFY_USER_TOKEN_PATH: &str					= "data/user_token.json";
 pub const RSPOTIFY_REDIRECT_URI: &str						= "http://localhost:8888/callback";
 pub const RSPOTIFY_SCOPES: [&str; 3]						= ["playlist-modify-public", "playlist-modify-private", "ugc-image-upload"];
 pub const RSPOTIFY_PLAYLIST_DESCRIPTION: &str				= "Playlist automatically created by Archify following an export of the archivied playlist.";
+pub const RSPOTIFY_MAX_ITEMS_PER_REQUEST: usize				= 100;
+pub const RSPOTIFY_PLAYLIST_COVER_URL: &str					= "https://api.spotify.com/v1/playlists/{}/images";
+
+// API retries
+pub const CONF_API_MAX_RETRIES: u32							= 3;
+// Seconds to wait before a retry when the API does not say
+pub const CONF_API_RETRY_DELAY: u64							= 5;
+pub const CONF_API_MAX_RETRY_AFTER: u64						= 5 * 60;
+
+// Export
+pub const CONF_DEFAULT_EXPORT_TEMPLATE: &str				= "Archify - {name} - {date}";
+
+// Cover
+pub const CONF_COVER_SIZE: u32								= 640;
+pub const CONF_COVER_JPEG_QUALITY: u8						= 90;
+pub const CONF_COVER_MAX_UPLOAD_SIZE: usize					= 256 * 1024;
+
+// Schedule
+// Spotify refreshes Discover Weekly on Monday and Release Radar on Friday
+pub const CONF_SCHEDULE_PRESETS: [(&str, &str); 3]			= [("discover-weekly", "0 0 6 * * Mon"), ("release-radar", "0 0 6 * * Fri"), ("daily", "0 0 6 * * *")];
+// A playlist due within this many seconds is updated, so that runs at a fixed time do not skip it for a few seconds
+pub const CONF_SCHEDULE_TOLERANCE: u64						= 5 * 60;
+
+// Daemon
+// Seconds between two checks for playlists added, resumed or rescheduled by other commands
+pub const CONF_DAEMON_RECHECK_INTERVAL: u64					= 5 * 60;
+// Seconds between two updates of a playlist without a schedule, which would otherwise be due at every check
+pub const CONF_DAEMON_UNSCHEDULED_INTERVAL: u64				= CONF_SECONDS_PER_DAY;
+
+// Notifications
+// Seconds given to a sink to take the events before it is considered failed
+pub const CONF_NOTIFY_TIMEOUT: u64							= 30;
+
+// Server
+pub const CONF_SERVE_ADDRESS: &str							= "127.0.0.1:8080";
+// Overrides serve_token of config.json, so that the token is not written in a file
+pub const CONF_SERVE_TOKEN_ENV: &str						= "ARCHIFY_SERVE_TOKEN";
+
+// Feeds
+pub const CONF_FEED_MAX_ENTRIES: usize						= 50;
+pub const CONF_FEED_GLOBAL_NAME: &str						= "all";
+
+// Stats
+pub const CONF_STATS_TOP_ARTISTS: usize						= 10;
 
 // Proxy
 #[cfg(feature = "proxy")]
@@ -30,4 +80,5 @@ pub const CONF_SHA256_NULL: [u8; 32]						= [0;32];
 pub const CONF_TIMESTAMP_NULL: u64							= 0;
 pub const CONF_NULL_PLAYLIST_DATA: Option<FullPlaylist> 	= None;
 pub const CONF_NULL_STRING: String							= String::new();
-pub const CONF_DEFAULT_COUNT: u64							= 0;
\ No newline at end of file
+pub const CONF_DEFAULT_COUNT: u64							= 0;
+pub const CONF_SECONDS_PER_DAY: u64							= 24 * 60 * 60;
\ No newline at end of file
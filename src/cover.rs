@@ -0,0 +1,117 @@
+use crate::conf::*;
+
+use std::io::Cursor;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, Rgb, RgbImage};
+use log::{error, info};
+use sha2::{Digest, Sha256};
+
+// 5x7 glyphs, one byte per row, lowest 5 bits used (MSB first).
+const FONT_WIDTH: u32 = 5;
+const FONT_HEIGHT: u32 = 7;
+
+fn glyph(c: char) -> [u8; 7] {
+	match c {
+		'0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+		'1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+		'2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+		'3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+		'4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+		'5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+		'6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+		'7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+		'8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+		'9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+		'-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+		'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+		'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+		'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+		'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+		'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+		'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+		'Y' => [0x11, 0x11, 0x0A, 0x04, 0x04, 0x04, 0x04],
+		_ => [0x00; 7]
+	}
+}
+
+fn draw_text(img: &mut RgbImage, text: &str, center_y: u32, scale: u32, color: Rgb<u8>){
+	let advance = (FONT_WIDTH + 1) * scale;
+	let width = advance * text.chars().count() as u32 - scale;
+	let x0 = (img.width().saturating_sub(width)) / 2;
+	let y0 = center_y.saturating_sub(FONT_HEIGHT * scale / 2);
+
+	for (i, c) in text.chars().enumerate(){
+		let rows = glyph(c);
+		for (row, bits) in rows.iter().enumerate(){
+			for col in 0..FONT_WIDTH{
+				if bits & (1 << (FONT_WIDTH - 1 - col)) == 0 {
+					continue;
+				}
+				for dy in 0..scale{
+					for dx in 0..scale{
+						let x = x0 + i as u32 * advance + col * scale + dx;
+						let y = y0 + row as u32 * scale + dy;
+						if x < img.width() && y < img.height(){
+							img.put_pixel(x, y, color);
+						}
+					}
+				}
+			}
+		}
+	}
+}
+
+/// Generates a cover stamped with the archive date (`YYYY-MM-DD`), its background being derived from `seed`.
+pub fn generate_cover(date: &str, seed: &str) -> Vec<u8>{
+	let hash = Sha256::digest(seed.as_bytes());
+	let background = Rgb([hash[0] / 2, hash[1] / 2, hash[2] / 2]);
+	let foreground = Rgb([255, 255, 255]);
+
+	let mut img = RgbImage::from_pixel(CONF_COVER_SIZE, CONF_COVER_SIZE, background);
+	draw_text(&mut img, "ARCHIFY", CONF_COVER_SIZE / 3, 12, foreground);
+	draw_text(&mut img, date, CONF_COVER_SIZE * 2 / 3, 10, foreground);
+
+	encode_jpeg(&DynamicImage::ImageRgb8(img), CONF_COVER_JPEG_QUALITY)
+}
+
+fn encode_jpeg(img: &DynamicImage, quality: u8) -> Vec<u8>{
+	let mut buf = Vec::new();
+	JpegEncoder::new_with_quality(&mut Cursor::new(&mut buf), quality)
+		.encode_image(&img.to_rgb8())
+		.unwrap();
+	buf
+}
+
+/// Re-encodes an archived cover as a JPEG whose base64 form fits in the Spotify upload limit.
+pub fn prepare_cover(data: &[u8]) -> Option<Vec<u8>>{
+	let img = match image::load_from_memory(data) {
+		Ok(img) => img,
+		Err(e) => {
+			error!("Archived cover cannot be decoded: {}", e);
+			return None;
+		}
+	};
+
+	let mut img = img.resize(CONF_COVER_SIZE, CONF_COVER_SIZE, FilterType::Lanczos3);
+	let mut quality = CONF_COVER_JPEG_QUALITY;
+
+	loop {
+		let jpeg = encode_jpeg(&img, quality);
+		// base64 grows the payload by 4/3
+		if jpeg.len().div_ceil(3) * 4 <= CONF_COVER_MAX_UPLOAD_SIZE {
+			info!("Cover re-encoded to {} bytes (quality {}, {}x{}).", jpeg.len(), quality, img.width(), img.height());
+			return Some(jpeg);
+		}
+
+		if quality > 40 {
+			quality -= 10;
+		}else if img.width() > 64 {
+			img = img.resize(img.width() / 2, img.height() / 2, FilterType::Lanczos3);
+		}else{
+			error!("Archived cover cannot fit in upload limit.");
+			return None;
+		}
+	}
+}
@@ -0,0 +1,118 @@
+use crate::archive::Archive;
+use crate::conf::*;
+use crate::metrics;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Local};
+use log::{error, info};
+use rspotify::model::PlaylistId;
+use rspotify::prelude::Id;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+
+fn now() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.unwrap()
+		.as_secs()
+}
+
+/// Like `Archive::next_due`, except that a playlist without a schedule is updated every
+/// `CONF_DAEMON_UNSCHEDULED_INTERVAL` rather than at every check.
+fn due_at(archive: &Archive, playlist_id: &PlaylistId) -> Option<u64> {
+	match archive.schedule(playlist_id) {
+		None => archive.next_due(playlist_id).map(|last_check| last_check + CONF_DAEMON_UNSCHEDULED_INTERVAL),
+		Some(_) => archive.next_due(playlist_id)
+	}
+}
+
+/// Updates the tracked playlists when they are due, until SIGTERM or Ctrl-C.
+/// The Spotify clients of `archive` are created once and their tokens refreshed across runs.
+/// The archive is checked again at least every `CONF_DAEMON_RECHECK_INTERVAL`, for changes made by other commands.
+pub async fn run(archive: &mut Archive){
+	let stop = Arc::new(AtomicBool::new(false));
+	let wake = Arc::new(Notify::new());
+	{
+		let stop = stop.clone();
+		let wake = wake.clone();
+		let mut sigterm = signal(SignalKind::terminate()).unwrap();
+		tokio::spawn(async move {
+			tokio::select! {
+				_ = sigterm.recv() => info!("SIGTERM received, stopping daemon."),
+				_ = tokio::signal::ctrl_c() => info!("Ctrl-C received, stopping daemon.")
+			}
+			stop.store(true, Ordering::SeqCst);
+			wake.notify_one();
+		});
+	}
+
+	metrics::set_long_running();
+	info!("Daemon started.");
+
+	while !stop.load(Ordering::SeqCst) {
+		let run = metrics::start_run();
+		let mut next_due = u64::MAX;
+		let mut updated = false;
+		let mut events = Vec::new();
+
+		for p in archive.db().get_latest_unique_playlists() {
+			// A playlist is only interrupted between two updates, never in the middle of one
+			if stop.load(Ordering::SeqCst) {
+				break;
+			}
+
+			let mut due = match due_at(archive, &p.id) {
+				Some(due) => due,
+				None => continue
+			};
+			if due <= now() {
+				updated = true;
+				due = match archive.update_playlist(&p).await {
+					Ok(event) => {
+						events.extend(event);
+						match due_at(archive, &p.id) {
+							// The update failed and the playlist is still due, tried again at the next check
+							Some(due) if due <= now() => now() + CONF_DAEMON_RECHECK_INTERVAL,
+							due => due.unwrap_or(u64::MAX)
+						}
+					},
+					Err(e) => {
+						// Spotify cannot be reached, tried again at the next check
+						error!("Playlist {} cannot be updated: {}", p.id.id(), e);
+						events.push(archive.update_failed(&p, &e));
+						now() + CONF_DAEMON_RECHECK_INTERVAL
+					}
+				};
+			}
+			next_due = next_due.min(due);
+		}
+
+		// Most checks find nothing due, and would replace the metrics of the last actual run
+		if updated {
+			archive.finish_updates(&events, run).await;
+		}
+
+		if stop.load(Ordering::SeqCst) {
+			break;
+		}
+
+		let recheck = now() + CONF_DAEMON_RECHECK_INTERVAL;
+		if next_due == u64::MAX {
+			info!("No playlist to update.");
+		}else{
+			let date = DateTime::<Local>::from(UNIX_EPOCH + Duration::from_secs(next_due));
+			info!("Next update at {}.", date.format("%v %X"));
+		}
+		let sleep = Duration::from_secs(next_due.min(recheck).saturating_sub(now()));
+
+		tokio::select! {
+			_ = tokio::time::sleep(sleep) => (),
+			_ = wake.notified() => ()
+		}
+	}
+
+	info!("Daemon stopped.");
+}
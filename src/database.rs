@@ -1,44 +1,556 @@
 use crate::conf::*;
+use crate::items::{self, Item, ItemKind};
+use crate::retention::RetentionPolicy;
 
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
 use log::info;
 use rspotify::model::{FullPlaylist, PlaylistId};
 use rspotify::prelude::Id;
-use rusqlite::{params,Connection};
+use rusqlite::{params,Connection,Row};
+use sha2::{Digest, Sha256};
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+	Tracks,
+	Cover,
+	Name,
+	Description,
+	Owner,
+	Public,
+	Collaborative,
+	Followers
+}
+
+impl Change {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Change::Tracks => "tracks",
+			Change::Cover => "cover",
+			Change::Name => "name",
+			Change::Description => "description",
+			Change::Owner => "owner",
+			Change::Public => "public",
+			Change::Collaborative => "collaborative",
+			Change::Followers => "followers"
+		}
+	}
+
+	fn parse_list(list: &str) -> Vec<Change> {
+		list.split(',')
+			.filter_map(|c| match c {
+				"tracks" => Some(Change::Tracks),
+				"cover" => Some(Change::Cover),
+				"name" => Some(Change::Name),
+				"description" => Some(Change::Description),
+				"owner" => Some(Change::Owner),
+				"public" => Some(Change::Public),
+				"collaborative" => Some(Change::Collaborative),
+				"followers" => Some(Change::Followers),
+				_ => None
+			})
+			.collect()
+	}
+
+	pub fn format_list(changes: &[Change]) -> String {
+		changes.iter().map(|c| c.as_str()).collect::<Vec<_>>().join(",")
+	}
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct PlaylistMetadata {
+	pub name: String,
+	pub description: Option<String>,
+	pub owner_id: String,
+	pub public: Option<bool>,
+	pub collaborative: bool,
+	pub followers: u32
+}
+
+#[derive(Clone)]
 pub struct Playlist {
 	pub id: PlaylistId<'static>,
 	pub sha256:  [u8; 32],
+	/// SHA256 of the items only, `None` for versions archived before it was recorded.
+	pub tracks_sha256: Option<[u8; 32]>,
+	pub cover_sha256: Option<[u8; 32]>,
 	pub timestamp: u64,
 	pub count: u64,
-	pub data: Option<FullPlaylist>
+	pub metadata: Option<PlaylistMetadata>,
+	/// What changed since the previous version, `None` when unknown.
+	pub changes: Option<Vec<Change>>,
+	pub data: Option<FullPlaylist>,
+	/// Cover image bytes, only loaded when a single version is retrieved.
+	pub cover: Option<Vec<u8>>
+}
+
+impl Playlist {
+	/// Whether the cover of this version is unknown, rather than absent: versions archived before covers were, and the
+	/// placeholder, have none stored.
+	fn cover_unknown(&self) -> bool {
+		self.cover_sha256.is_none() && self.data.as_ref().is_none_or(|d| !d.images.is_empty())
+	}
+
+	/// Lists what differs between `previous` and this version. A field unknown on either side is not reported.
+	pub fn changes_since(&self, previous: &Playlist) -> Vec<Change> {
+		let mut changes = Vec::new();
+
+		if let (Some(new), Some(old)) = (self.tracks_sha256, previous.tracks_sha256) {
+			if new != old {
+				changes.push(Change::Tracks);
+			}
+		}
+		if !self.cover_unknown() && !previous.cover_unknown() && self.cover_sha256 != previous.cover_sha256 {
+			changes.push(Change::Cover);
+		}
+		if let (Some(new), Some(old)) = (&self.metadata, &previous.metadata) {
+			if new.name != old.name {
+				changes.push(Change::Name);
+			}
+			if new.description != old.description {
+				changes.push(Change::Description);
+			}
+			if new.owner_id != old.owner_id {
+				changes.push(Change::Owner);
+			}
+			if new.public != old.public {
+				changes.push(Change::Public);
+			}
+			if new.collaborative != old.collaborative {
+				changes.push(Change::Collaborative);
+			}
+			if new.followers != old.followers {
+				changes.push(Change::Followers);
+			}
+		}
+
+		changes
+	}
+
+	/// Whether this version must be archived after `previous`. A change of followers alone is only an observation.
+	pub fn is_new_version_of(&self, previous: &Playlist) -> bool {
+		// The placeholder has nothing to compare with
+		if self.tracks_sha256.is_none() || previous.tracks_sha256.is_none() {
+			return self.sha256 != previous.sha256;
+		}
+		self.changes_since(previous).iter().any(|c| !matches!(c, Change::Followers))
+	}
 }
 
 pub type Playlists = Vec<Playlist>;
 
+/// Version of a tracked playlist.
+pub enum Version {
+	/// Index as given by --tracked, the first version being 1.
+	Index(u64),
+	/// Latest version archived at or before a timestamp.
+	At(u64),
+	First,
+	Latest
+}
 
-pub struct Database {
-	client: Connection
+/// Timestamp of the end of a `YYYY-MM-DD` day in local time, or of an RFC 3339 date.
+fn parse_date(date: &str) -> Option<u64>{
+	if let Ok(day) = NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+		let end = day.and_hms_opt(23, 59, 59)?;
+		return Local.from_local_datetime(&end).latest().map(|d| d.timestamp() as u64);
+	}
+	DateTime::parse_from_rfc3339(date).ok().map(|d| d.timestamp() as u64)
+}
+
+impl Version {
+	/// Parses an index as given by --tracked, `first`, `latest` or `@DATE`.
+	pub fn parse(version: &str) -> Option<Version>{
+		if let Some(date) = version.strip_prefix('@') {
+			return parse_date(date).map(Version::At);
+		}
+		match version {
+			"first" => Some(Version::First),
+			"latest" => Some(Version::Latest),
+			_ => version.parse().ok().map(Version::Index)
+		}
+	}
+}
+
+/// Lightweight state of a playlist, recorded at every update even when no version is stored.
+pub struct Observation {
+	pub id: PlaylistId<'static>,
+	pub timestamp: u64,
+	pub followers: u32,
+	pub snapshot_id: String,
+	pub item_count: u32
+}
+
+/// Item of a version matching a full-text search.
+pub struct SearchHit {
+	pub playlist_id: PlaylistId<'static>,
+	pub playlist_name: Option<String>,
+	pub timestamp: u64,
+	pub uri: String,
+	pub name: String,
+	pub artists: Vec<String>,
+	pub album: Option<String>
+}
+
+pub struct ExportResult {
+	pub id: PlaylistId<'static>,
+	pub timestamp: u64,
+	pub export_timestamp: u64,
+	pub exported_id: Option<String>,
+	pub error: Option<String>
+}
+
+
+/// Playlist removed with --delete-playlist, no longer updated but whose versions are kept until purged.
+pub struct TrashEntry {
+	pub id: PlaylistId<'static>,
+	pub deleted_at: u64,
+	pub name: Option<String>,
+	pub versions: u64
+}
+
+
+/// Where archify keeps the tracked playlists: their versions with items and covers, settings, observations, exports
+/// and the trash.
+///
+/// A tracked playlist starts with a placeholder version at `CONF_TIME_BIG_BANG`, without data, so that its archived
+/// versions take the indexes given by --tracked, the first one being 1.
+pub trait Storage {
+	/// Rebuilds the full-text index searched by `search` from the archived items.
+	fn rebuild_search_index(&self);
+
+	/// Searches track titles, artists, albums and playlist names, best matches first.
+	fn search(&self, query: &str) -> Result<Vec<SearchHit>, String>;
+
+	/// Size of the storage in bytes.
+	fn get_size(&self) -> u64;
+
+	/// Timestamps of the archived versions of a playlist, the first one having index 1.
+	fn get_version_timestamps(&self, playlist_id: &PlaylistId) -> Vec<u64>;
+
+	/// Tracks a playlist by inserting its placeholder version, or restores it if it is in the trash.
+	fn set_unique_empty_playlist(&self, playlist_id: &PlaylistId);
+
+	/// Items of every version of a playlist, grouped by version timestamp in ascending order.
+	fn get_all_items(&self, playlist_id: &PlaylistId) -> Vec<(u64, Vec<Item>)>;
+
+	/// Items of the version archived at `timestamp`.
+	fn get_version_items(&self, playlist_id: &PlaylistId, timestamp: u64) -> Vec<Item>;
+
+	/// Playlists having at least one version containing `uri`.
+	fn get_playlists_containing(&self, uri: &str) -> Vec<PlaylistId<'static>>;
+
+	/// Name of the latest version of a playlist.
+	fn get_playlist_name(&self, playlist_id: &PlaylistId) -> Option<String>;
+
+	fn get_cover(&self, sha256: &[u8; 32]) -> Option<Vec<u8>>;
+
+	/// Inserts a new version, with its cover and items.
+	fn set_playlist(&self, playlist: &Playlist);
+
+	fn set_observation(&self, observation: &Observation);
+
+	/// Time of the last check of a playlist, whether it made a new version or not. 0 if it was never checked.
+	fn get_last_check(&self, playlist_id: &PlaylistId) -> u64;
+
+	/// Observations of a playlist in ascending order.
+	fn get_observations(&self, playlist_id: &PlaylistId) -> Vec<Observation>;
+
+	/// Deletes a playlist with all its versions, settings and exports. Covers left without any version are deleted as well.
+	fn delete_playlist(&self, playlist_id: &PlaylistId);
+
+	/// Stops tracking a playlist while keeping its versions, until it is restored or purged.
+	fn trash_playlist(&self, playlist_id: &PlaylistId, deleted_at: u64);
+
+	/// Tracks a trashed playlist again. Returns false if it was not in the trash.
+	fn restore_playlist(&self, playlist_id: &PlaylistId) -> bool;
+
+	/// Trashed playlists, oldest deletion first.
+	fn get_trashed_playlists(&self) -> Vec<TrashEntry>;
+
+	/// Enables or disables auto-export. A `None` template keeps the previous one.
+	fn set_auto_export(&self, playlist_id: &PlaylistId, enabled: bool, template: Option<&str>);
+
+	/// Returns the naming template to use if auto-export is enabled for the playlist.
+	fn get_auto_export(&self, playlist_id: &PlaylistId) -> Option<String>;
+
+	/// Sets the retention policy of a playlist, `None` falling back to the global one.
+	fn set_retention(&self, playlist_id: &PlaylistId, policy: Option<&RetentionPolicy>);
+
+	/// Returns the retention policy of the playlist if one is set.
+	fn get_retention(&self, playlist_id: &PlaylistId) -> Option<RetentionPolicy>;
+
+	/// Sets the update schedule of a playlist, `None` falling back to the global one.
+	fn set_schedule(&self, playlist_id: &PlaylistId, schedule: Option<&str>);
+
+	fn get_schedule(&self, playlist_id: &PlaylistId) -> Option<String>;
+
+	/// A paused playlist is kept but no longer updated, unless forced.
+	fn set_paused(&self, playlist_id: &PlaylistId, paused: bool);
+
+	fn is_paused(&self, playlist_id: &PlaylistId) -> bool;
+
+	/// A pinned version is never deleted by the retention policy.
+	fn set_pinned(&self, playlist_id: &PlaylistId, timestamp: u64, pinned: bool);
+
+	fn get_pinned_timestamps(&self, playlist_id: &PlaylistId) -> Vec<u64>;
+
+	/// Deletes a single version with its items. Covers left without any version are deleted as well.
+	fn delete_version(&self, playlist_id: &PlaylistId, timestamp: u64);
+
+	fn set_export_result(&self, export: &ExportResult);
+
+	/// Timestamps of the versions of a playlist that were successfully exported.
+	fn get_exported_timestamps(&self, playlist_id: &PlaylistId) -> Vec<u64>;
+
+	/// Latest version of every tracked playlist outside the trash, with its number of versions, placeholder included,
+	/// as `count`.
+	fn get_latest_unique_playlists(&self) -> Playlists;
+
+	/// Every version of a playlist in ascending order, placeholder first, each with its index as `count`.
+	fn get_all_tracked_versions(&self, playlist_id: &PlaylistId) -> Playlists;
+
+	/// Latest `limit` versions of a playlist in ascending order, each with its index as `count`, the placeholder
+	/// included if there are not that many.
+	fn get_latest_tracked_versions(&self, playlist_id: &PlaylistId, limit: u64) -> Playlists;
+
+	/// Version of a playlist at `index`, 0 being the placeholder, with its cover loaded.
+	fn get_playlist_from_tracked_index(&self, playlist_id: &PlaylistId, index: u64) -> Option<Playlist>;
+
+	/// Index, as given by --tracked, of a version. `None` if the playlist has no such version.
+	fn get_version_index(&self, playlist_id: &PlaylistId, version: &Version) -> Option<u64>{
+		let timestamps = self.get_version_timestamps(playlist_id);
+		let count = timestamps.len() as u64;
+
+		match version {
+			Version::Index(index) => Some(*index).filter(|i| (1..=count).contains(i)),
+			Version::At(timestamp) => Some(timestamps.iter().filter(|t| *t <= timestamp).count() as u64).filter(|c| *c > 0),
+			Version::First => Some(1).filter(|_| count > 0),
+			Version::Latest => Some(count).filter(|c| *c > 0)
+		}
+	}
 }
 
 
-impl Database {
-	pub fn new() -> Self {
-		let con = Connection::open(CONF_DATABASE_PATH).unwrap();		
+/// Storage in a SQLite database, by default at `CONF_DATABASE_PATH`.
+pub struct SqliteStorage {
+	client: Connection
+}
+
+impl SqliteStorage {
+	/// Opens the database at `path`, `:memory:` for one that only lives as long as the storage, and creates or
+	/// migrates its tables.
+	pub fn open(path: &str) -> rusqlite::Result<Self> {
+		let con = Connection::open(path)?;
 
-		let db  = Database { 
+		let db  = SqliteStorage { 
 			client: con 
 		};
 
-		db.create_tables();
+		db.create_tables()?;
+
+		Ok(db)
+	}
+
+	fn create_tables(&self) -> rusqlite::Result<()>{
+		self.client.execute("CREATE TABLE IF NOT EXISTS playlists (playlist_id TEXT, playlist_SHA256 BLOB, timestamp TIMESTAMP, playlist_data TEXT, PRIMARY KEY (playlist_id, timestamp))", ())?;
+		self.client.execute("CREATE TABLE IF NOT EXISTS playlist_settings (playlist_id TEXT PRIMARY KEY, auto_export INTEGER NOT NULL DEFAULT 0, export_template TEXT)", ())?;
+		self.client.execute("CREATE TABLE IF NOT EXISTS covers (cover_sha256 BLOB PRIMARY KEY, cover_data BLOB)", ())?;
+		self.client.execute("CREATE TABLE IF NOT EXISTS exports (playlist_id TEXT, timestamp TIMESTAMP, export_timestamp TIMESTAMP, exported_playlist_id TEXT, error TEXT)", ())?;
+		self.client.execute("CREATE TABLE IF NOT EXISTS observations (playlist_id TEXT, timestamp TIMESTAMP, followers INTEGER, snapshot_id TEXT, item_count INTEGER, PRIMARY KEY (playlist_id, timestamp))", ())?;
+		self.client.execute("CREATE TABLE IF NOT EXISTS trash (playlist_id TEXT PRIMARY KEY, deleted_at TIMESTAMP)", ())?;
+		let index_items = !self.table_exists("playlist_items");
+		self.client.execute("CREATE TABLE IF NOT EXISTS playlist_items (playlist_id TEXT, timestamp TIMESTAMP, position INTEGER, kind TEXT, uri TEXT, name TEXT, artists TEXT, album TEXT, show TEXT, release_date TEXT, duration_ms INTEGER, added_at TEXT, added_by TEXT, PRIMARY KEY (playlist_id, timestamp, position))", ())?;
+		self.client.execute("CREATE INDEX IF NOT EXISTS playlist_items_uri ON playlist_items (uri)", ())?;
+		let index_search = !self.table_exists("search_index");
+		self.client.execute("CREATE VIRTUAL TABLE IF NOT EXISTS search_index USING fts5(playlist_id UNINDEXED, timestamp UNINDEXED, position UNINDEXED, uri UNINDEXED, name, artists, album, playlist_name)", ())?;
+		self.add_missing_column("playlists", "cover_sha256", "BLOB")?;
+		self.add_missing_column("playlists", "tracks_sha256", "BLOB")?;
+		self.add_missing_column("playlists", "name", "TEXT")?;
+		self.add_missing_column("playlists", "description", "TEXT")?;
+		self.add_missing_column("playlists", "owner_id", "TEXT")?;
+		self.add_missing_column("playlists", "public", "INTEGER")?;
+		self.add_missing_column("playlists", "collaborative", "INTEGER")?;
+		self.add_missing_column("playlists", "followers", "INTEGER")?;
+		self.add_missing_column("playlists", "changes", "TEXT")?;
+		self.add_missing_column("playlists", "pinned", "INTEGER NOT NULL DEFAULT 0")?;
+		self.add_missing_column("playlist_settings", "keep_last", "INTEGER")?;
+		self.add_missing_column("playlist_settings", "keep_days", "INTEGER")?;
+		self.add_missing_column("playlist_settings", "keep_daily", "INTEGER")?;
+		self.add_missing_column("playlist_settings", "keep_weekly", "INTEGER")?;
+		self.add_missing_column("playlist_settings", "keep_monthly", "INTEGER")?;
+		self.add_missing_column("playlist_settings", "schedule", "TEXT")?;
+		self.add_missing_column("playlist_settings", "paused", "INTEGER NOT NULL DEFAULT 0")?;
+
+		// Versions archived before the items had their own hash, when the hash of the version only covered them
+		self.client.execute(
+			"UPDATE playlists SET tracks_sha256 = playlist_SHA256 WHERE tracks_sha256 IS NULL AND playlist_SHA256 IS NOT NULL AND timestamp != ?1",
+			params![CONF_TIME_BIG_BANG]
+		)?;
+		// Versions archived before metadata had its own columns
+		self.client.execute(
+			"UPDATE playlists SET name = json_extract(playlist_data, '$.name'), description = json_extract(playlist_data, '$.description'), owner_id = json_extract(playlist_data, '$.owner.id'), public = json_extract(playlist_data, '$.public'), collaborative = json_extract(playlist_data, '$.collaborative'), followers = json_extract(playlist_data, '$.followers.total') WHERE name IS NULL AND timestamp != ?1",
+			params![CONF_TIME_BIG_BANG]
+		)?;
+
+		if index_items {
+			self.index_all_items();
+		}
+		if index_search {
+			self.rebuild_search_index();
+		}
+
+		Ok(())
+	}
+
+	/// Copies items, with the name of their version, into the full-text index.
+	fn index_search(&self, condition: &str, parameters: &[&dyn rusqlite::ToSql]){
+		self.client.execute(
+			&format!(
+				"INSERT INTO search_index (playlist_id, timestamp, position, uri, name, artists, album, playlist_name) SELECT playlist_items.playlist_id, playlist_items.timestamp, playlist_items.position, playlist_items.uri, playlist_items.name, playlist_items.artists, COALESCE(playlist_items.album, playlist_items.show), playlists.name FROM playlist_items JOIN playlists ON playlists.playlist_id = playlist_items.playlist_id AND playlists.timestamp = playlist_items.timestamp {}",
+				condition
+			),
+			parameters
+		).unwrap();
+	}
+
+	fn table_exists(&self, table: &str) -> bool {
+		self.client.query_row(
+			"SELECT name FROM sqlite_master WHERE type = 'table' AND name = ?1",
+			params![table],
+			|_| Ok(())
+		).is_ok()
+	}
+
+	/// Fills `playlist_items` from the versions archived before it existed.
+	fn index_all_items(&self){
+		let mut query = self.client.prepare("SELECT playlist_id, timestamp, playlist_data FROM playlists WHERE timestamp != ?1").unwrap();
+		let versions: Vec<(String, u64, String)> = query
+			.query_map(params![CONF_TIME_BIG_BANG], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+			.unwrap()
+			.map(|v| v.unwrap())
+			.collect();
+
+		for (serialized_id, timestamp, serialized_data) in &versions {
+			let playlist_id: PlaylistId = serde_json::from_str(serialized_id).unwrap();
+			let data: Option<FullPlaylist> = serde_json::from_str(serialized_data).unwrap();
+			if let Some(data) = data {
+				self.set_items(&playlist_id, *timestamp, &items::get_items(&data));
+			}
+		}
+		info!("Items of {} archived versions indexed.", versions.len());
+	}
+
+	fn add_missing_column(&self, table: &str, column: &str, definition: &str) -> rusqlite::Result<()>{
+		let mut query = self.client.prepare(&format!("SELECT name FROM pragma_table_info('{}')", table))?;
+		let names = query
+			.query_map([], |row| row.get::<_, String>("name"))?
+			.collect::<rusqlite::Result<Vec<String>>>()?;
+
+		if !names.iter().any(|name| name.eq_ignore_ascii_case(column)) {
+			self.client.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), ())?;
+			info!("Column {} added to table {}.", column, table);
+		}
+		Ok(())
+	}
+
+	fn set_items(&self, playlist_id: &PlaylistId, timestamp: u64, items: &[Item]){
+		let serialized_id = serde_json::to_string(playlist_id).unwrap();
+		let transaction = self.client.unchecked_transaction().unwrap();
+		let mut query = transaction.prepare("INSERT OR REPLACE INTO playlist_items (playlist_id, timestamp, position, kind, uri, name, artists, album, show, release_date, duration_ms, added_at, added_by) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)").unwrap();
+		for item in items {
+			query.execute(
+				params![
+					serialized_id,
+					timestamp,
+					item.position,
+					item.kind.as_str(),
+					item.uri,
+					item.name,
+					serde_json::to_string(&item.artists).unwrap(),
+					item.album,
+					item.show,
+					item.release_date,
+					item.duration_ms,
+					item.added_at,
+					item.added_by
+				]
+			).unwrap();
+		}
+		drop(query);
+		transaction.commit().unwrap();
+	}
+
+	/// Stores a cover once per content, returning its SHA256.
+	fn set_cover(&self, cover: &[u8]) -> [u8; 32]{
+		let sha256: [u8; 32] = Sha256::digest(cover).into();
+		self.client.execute(
+			"INSERT OR IGNORE INTO covers (cover_sha256, cover_data) VALUES (?1, ?2)",
+			params![
+				sha256,
+				cover
+			]
+		).unwrap();
+
+		sha256
+	}
+
+	/// Deletes the covers left without any version.
+	fn delete_unused_covers(&self){
+		self.client.execute(
+			"DELETE FROM covers WHERE cover_sha256 NOT IN (SELECT cover_sha256 FROM playlists WHERE cover_sha256 IS NOT NULL)",
+			()
+		).unwrap();
+	}
+
+	/// Runs an FTS5 query over the index, best matches first.
+	fn query_search_index(&self, query: &str) -> rusqlite::Result<Vec<SearchHit>>{
+		let mut statement = self.client.prepare("SELECT * FROM search_index WHERE search_index MATCH ?1 ORDER BY rank")?;
+		let h_iter = statement.query_map([query], |row| {
+			Ok(
+				SearchHit {
+					playlist_id: serde_json::from_str(&row.get::<_, String>("playlist_id")?).unwrap(),
+					playlist_name: row.get("playlist_name")?,
+					timestamp: row.get("timestamp")?,
+					uri: row.get("uri")?,
+					name: row.get("name")?,
+					artists: serde_json::from_str(&row.get::<_, String>("artists")?).unwrap(),
+					album: row.get("album")?
+				}
+			)
+		})?;
+
+		h_iter.collect()
+	}
+}
+
+impl Storage for SqliteStorage {
+	fn rebuild_search_index(&self){
+		self.client.execute("DELETE FROM search_index", ()).unwrap();
+		self.index_search("", &[]);
+		info!("Search index rebuilt.");
+	}
+
+	fn search(&self, query: &str) -> Result<Vec<SearchHit>, String>{
+		self.query_search_index(query).map_err(|e| e.to_string())
+	}
 
-		db
+	fn get_size(&self) -> u64{
+		self.client.query_row(
+			"SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+			(),
+			|row| row.get(0)
+		).unwrap()
 	}
 
-	fn create_tables(&self){
-		self.client.execute("CREATE TABLE IF NOT EXISTS playlists (playlist_id TEXT, playlist_SHA256 BLOB, timestamp TIMESTAMP, playlist_data TEXT, PRIMARY KEY (playlist_id, timestamp))", ()).unwrap();
+	fn get_version_timestamps(&self, playlist_id: &PlaylistId) -> Vec<u64>{
+		let serialized_id = serde_json::to_string(playlist_id).unwrap();
+		let mut query = self.client.prepare("SELECT timestamp FROM playlists WHERE playlist_id = ?1 AND timestamp != ?2 ORDER BY timestamp ASC").unwrap();
+		let t_iter = query.query_map(params![serialized_id, CONF_TIME_BIG_BANG], |row| row.get::<_, u64>("timestamp")).unwrap();
+
+		t_iter.map(|t| t.unwrap()).collect()
 	}
 
-	pub fn set_unique_empty_playlist(&self, playlist_id: &PlaylistId){
+	fn set_unique_empty_playlist(&self, playlist_id: &PlaylistId){
 		let serialized_id = serde_json::to_string(playlist_id).unwrap();
 
 		let res = self.client.query_row(
@@ -50,6 +562,7 @@ impl Database {
 		);
 
 		match res {
+			Result::Ok(_) if self.restore_playlist(playlist_id) => (),
 			Result::Ok(_) => info!("Playlist {} is already present.", playlist_id),
 			Result::Err(_) => {
 				self.client.execute(
@@ -65,55 +578,400 @@ impl Database {
 		};
 	}
 
-	pub fn set_playlist(&self, playlist: &Playlist){
+	fn get_all_items(&self, playlist_id: &PlaylistId) -> Vec<(u64, Vec<Item>)>{
+		let serialized_id = serde_json::to_string(playlist_id).unwrap();
+		// Versions without any item are kept, so that indexes match --tracked
+		let mut query = self.client.prepare("SELECT playlists.timestamp AS version_timestamp, playlist_items.* FROM playlists LEFT JOIN playlist_items ON playlists.playlist_id = playlist_items.playlist_id AND playlists.timestamp = playlist_items.timestamp WHERE playlists.playlist_id = ?1 AND playlists.timestamp != ?2 ORDER BY playlists.timestamp ASC, playlist_items.position ASC").unwrap();
+		let i_iter = query.query_map(params![serialized_id, CONF_TIME_BIG_BANG], |row| {
+			let item = match row.get::<_, Option<usize>>("position")? {
+				Some(_) => Some(row_to_item(row)?),
+				None => None
+			};
+			Ok((row.get::<_, u64>("version_timestamp")?, item))
+		}).unwrap();
+
+		let mut versions: Vec<(u64, Vec<Item>)> = Vec::new();
+		for i in i_iter {
+			let (timestamp, item) = i.unwrap();
+			if versions.last().map(|(t, _)| *t) != Some(timestamp) {
+				versions.push((timestamp, Vec::new()));
+			}
+			if let Some(item) = item {
+				versions.last_mut().unwrap().1.push(item);
+			}
+		}
+
+		versions
+	}
+
+	fn get_version_items(&self, playlist_id: &PlaylistId, timestamp: u64) -> Vec<Item>{
+		let serialized_id = serde_json::to_string(playlist_id).unwrap();
+		let mut query = self.client.prepare("SELECT * FROM playlist_items WHERE playlist_id = ?1 AND timestamp = ?2 ORDER BY position ASC").unwrap();
+		let i_iter = query.query_map(params![serialized_id, timestamp], row_to_item).unwrap();
+
+		i_iter.map(|i| i.unwrap()).collect()
+	}
+
+	fn get_playlists_containing(&self, uri: &str) -> Vec<PlaylistId<'static>>{
+		let mut query = self.client.prepare("SELECT DISTINCT playlist_id FROM playlist_items WHERE uri = ?1").unwrap();
+		let p_iter = query.query_map([uri], |row| row.get::<_, String>("playlist_id")).unwrap();
+
+		p_iter.map(|p| serde_json::from_str(&p.unwrap()).unwrap()).collect()
+	}
+
+	fn get_playlist_name(&self, playlist_id: &PlaylistId) -> Option<String>{
+		let serialized_id = serde_json::to_string(playlist_id).unwrap();
+		self.client.query_row(
+			"SELECT name FROM playlists WHERE playlist_id = ?1 AND name IS NOT NULL ORDER BY timestamp DESC LIMIT 1",
+			params![serialized_id],
+			|row| row.get("name")
+		).ok()
+	}
+
+	fn get_cover(&self, sha256: &[u8; 32]) -> Option<Vec<u8>>{
+		self.client.query_row(
+			"SELECT cover_data FROM covers WHERE cover_sha256 = ?1",
+			params![sha256],
+			|row| row.get(0)
+		).ok()
+	}
+
+	fn set_playlist(&self, playlist: &Playlist){
 		let serialized_id = serde_json::to_string(&playlist.id).unwrap();
 		let serialized_data = serde_json::to_string(&playlist.data).unwrap();
+		if let Some(cover) = &playlist.cover {
+			self.set_cover(cover);
+		}
+		if let Some(data) = &playlist.data {
+			self.set_items(&playlist.id, playlist.timestamp, &items::get_items(data));
+		}
+		let metadata = playlist.metadata.as_ref();
 		self.client.execute(
-			"INSERT INTO playlists (playlist_id, playlist_SHA256, timestamp, playlist_data) VALUES (?1, ?2, ?3, ?4)",
+			"INSERT INTO playlists (playlist_id, playlist_SHA256, timestamp, playlist_data, cover_sha256, tracks_sha256, name, description, owner_id, public, collaborative, followers, changes) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
 			params![
 				serialized_id,
 				playlist.sha256,
 				playlist.timestamp,
-				serialized_data
+				serialized_data,
+				playlist.cover_sha256,
+				playlist.tracks_sha256,
+				metadata.map(|m| &m.name),
+				metadata.and_then(|m| m.description.as_ref()),
+				metadata.map(|m| &m.owner_id),
+				metadata.and_then(|m| m.public),
+				metadata.map(|m| m.collaborative),
+				metadata.map(|m| m.followers),
+				playlist.changes.as_ref().map(|c| Change::format_list(c))
 			]
 		).unwrap();
+		self.index_search("WHERE playlist_items.playlist_id = ?1 AND playlist_items.timestamp = ?2", &[&serialized_id, &playlist.timestamp]);
 		info!("Playlist {} inserted.", playlist.id.id());
 	}
 
-	pub fn delete_playlist(&self, playlist_id: &PlaylistId){
+	fn set_observation(&self, observation: &Observation){
+		let serialized_id = serde_json::to_string(&observation.id).unwrap();
+		self.client.execute(
+			"INSERT OR REPLACE INTO observations (playlist_id, timestamp, followers, snapshot_id, item_count) VALUES (?1, ?2, ?3, ?4, ?5)",
+			params![
+				serialized_id,
+				observation.timestamp,
+				observation.followers,
+				observation.snapshot_id,
+				observation.item_count
+			]
+		).unwrap();
+	}
+
+	fn get_last_check(&self, playlist_id: &PlaylistId) -> u64{
+		let serialized_id = serde_json::to_string(playlist_id).unwrap();
+		self.client.query_row(
+			"SELECT COALESCE(MAX(timestamp), 0) FROM (SELECT timestamp FROM observations WHERE playlist_id = ?1 UNION ALL SELECT timestamp FROM playlists WHERE playlist_id = ?1)",
+			params![serialized_id],
+			|row| row.get(0)
+		).unwrap()
+	}
+
+	fn get_observations(&self, playlist_id: &PlaylistId) -> Vec<Observation>{
+		let serialized_id = serde_json::to_string(playlist_id).unwrap();
+		let mut query = self.client.prepare("SELECT * FROM observations WHERE playlist_id = ?1 ORDER BY timestamp ASC").unwrap();
+		let o_iter = query.query_map([serialized_id], |row| {
+			Ok(
+				Observation {
+					id: playlist_id.clone_static(),
+					timestamp: row.get("timestamp")?,
+					followers: row.get("followers")?,
+					snapshot_id: row.get("snapshot_id")?,
+					item_count: row.get("item_count")?
+				}
+			)
+		}).unwrap();
+
+		o_iter.map(|o| o.unwrap()).collect()
+	}
+
+	fn delete_playlist(&self, playlist_id: &PlaylistId){
 		let serialized_id = serde_json::to_string(playlist_id).unwrap();
 		self.client.execute(
 			"DELETE FROM playlists WHERE playlist_id = ?1",
 			params![serialized_id]
 		).unwrap();
+		self.client.execute(
+			"DELETE FROM playlist_settings WHERE playlist_id = ?1",
+			params![serialized_id]
+		).unwrap();
+		self.client.execute(
+			"DELETE FROM observations WHERE playlist_id = ?1",
+			params![serialized_id]
+		).unwrap();
+		self.client.execute(
+			"DELETE FROM playlist_items WHERE playlist_id = ?1",
+			params![serialized_id]
+		).unwrap();
+		self.client.execute(
+			"DELETE FROM search_index WHERE playlist_id = ?1",
+			params![serialized_id]
+		).unwrap();
+		self.client.execute(
+			"DELETE FROM trash WHERE playlist_id = ?1",
+			params![serialized_id]
+		).unwrap();
+		self.client.execute(
+			"DELETE FROM exports WHERE playlist_id = ?1",
+			params![serialized_id]
+		).unwrap();
+		self.delete_unused_covers();
 		info!("Playlist(s) {} deleted.", playlist_id);
 	}
 
-	pub fn get_latest_unique_playlists(&self) -> Playlists {
-		let mut playlists = Playlists::new();
+	fn trash_playlist(&self, playlist_id: &PlaylistId, deleted_at: u64){
+		let serialized_id = serde_json::to_string(playlist_id).unwrap();
+		let res = self.client.execute(
+			"INSERT OR IGNORE INTO trash (playlist_id, deleted_at) SELECT DISTINCT playlist_id, ?2 FROM playlists WHERE playlist_id = ?1",
+			params![serialized_id, deleted_at]
+		).unwrap();
 
-		let mut query = self.client.prepare("SELECT playlist_id, playlist_sha256, MAX(timestamp) as timestamp, COUNT(playlist_id) as count, playlist_data FROM playlists GROUP BY playlist_id").unwrap();
-		let p_iter = query.query_map([], |row| {
+		match res {
+			0 => info!("Playlist {} is not tracked.", playlist_id),
+			_ => info!("Playlist {} moved to trash.", playlist_id)
+		}
+	}
+
+	fn restore_playlist(&self, playlist_id: &PlaylistId) -> bool{
+		let serialized_id = serde_json::to_string(playlist_id).unwrap();
+		let res = self.client.execute(
+			"DELETE FROM trash WHERE playlist_id = ?1",
+			params![serialized_id]
+		).unwrap();
+
+		if res > 0 {
+			info!("Playlist {} restored from trash.", playlist_id);
+		}
+		res > 0
+	}
+
+	fn get_trashed_playlists(&self) -> Vec<TrashEntry>{
+		let mut query = self.client.prepare(
+			"SELECT trash.playlist_id, trash.deleted_at, (SELECT name FROM playlists WHERE playlists.playlist_id = trash.playlist_id AND name IS NOT NULL ORDER BY timestamp DESC LIMIT 1) AS name, (SELECT COUNT(*) FROM playlists WHERE playlists.playlist_id = trash.playlist_id AND timestamp != ?1) AS versions FROM trash ORDER BY trash.deleted_at ASC"
+		).unwrap();
+		let t_iter = query.query_map(params![CONF_TIME_BIG_BANG], |row| {
 			Ok(
-				Playlist {
-					id: {
-						let res: String = row.get("playlist_id").unwrap();
-						serde_json::from_str(res.as_str()).unwrap()
-					},
-					sha256: row.get("playlist_sha256").unwrap_or_else(|_| {CONF_SHA256_NULL}),
-					timestamp: row.get("timestamp").unwrap_or_else(|_| {CONF_TIMESTAMP_NULL}),
-					count: row.get("count").unwrap(),
-					data: {
-						let res: String = row.get("playlist_data").unwrap_or_else(|_| {CONF_NULL_STRING});
-						if !res.is_empty() {
-							serde_json::from_str(res.as_str()).unwrap()
-						} else {
-							CONF_NULL_PLAYLIST_DATA
-						}
-					}
+				TrashEntry {
+					id: serde_json::from_str(&row.get::<_, String>("playlist_id")?).unwrap(),
+					deleted_at: row.get("deleted_at")?,
+					name: row.get("name")?,
+					versions: row.get("versions")?
 				}
 			)
-		});
+		}).unwrap();
+
+		t_iter.map(|t| t.unwrap()).collect()
+	}
+
+	fn set_auto_export(&self, playlist_id: &PlaylistId, enabled: bool, template: Option<&str>){
+		let serialized_id = serde_json::to_string(playlist_id).unwrap();
+		self.client.execute(
+			"INSERT INTO playlist_settings (playlist_id, auto_export, export_template) VALUES (?1, ?2, ?3) ON CONFLICT(playlist_id) DO UPDATE SET auto_export = excluded.auto_export, export_template = COALESCE(excluded.export_template, export_template)",
+			params![
+				serialized_id,
+				enabled,
+				template
+			]
+		).unwrap();
+		info!("Auto-export of playlist {} set to {}.", playlist_id.id(), enabled);
+	}
+
+	fn get_auto_export(&self, playlist_id: &PlaylistId) -> Option<String>{
+		let serialized_id = serde_json::to_string(playlist_id).unwrap();
+
+		let res = self.client.query_row(
+			"SELECT export_template FROM playlist_settings WHERE playlist_id = ?1 AND auto_export = 1",
+			params![serialized_id],
+			|row| {
+				let template: Option<String> = row.get("export_template")?;
+				Ok(template.unwrap_or_else(|| String::from(CONF_DEFAULT_EXPORT_TEMPLATE)))
+			}
+		);
+
+		res.ok()
+	}
+
+	fn set_retention(&self, playlist_id: &PlaylistId, policy: Option<&RetentionPolicy>){
+		let serialized_id = serde_json::to_string(playlist_id).unwrap();
+		let policy = policy.cloned().unwrap_or_default();
+		self.client.execute(
+			"INSERT INTO playlist_settings (playlist_id, keep_last, keep_days, keep_daily, keep_weekly, keep_monthly) VALUES (?1, ?2, ?3, ?4, ?5, ?6) ON CONFLICT(playlist_id) DO UPDATE SET keep_last = excluded.keep_last, keep_days = excluded.keep_days, keep_daily = excluded.keep_daily, keep_weekly = excluded.keep_weekly, keep_monthly = excluded.keep_monthly",
+			params![
+				serialized_id,
+				policy.keep_last,
+				policy.keep_days,
+				policy.keep_daily,
+				policy.keep_weekly,
+				policy.keep_monthly
+			]
+		).unwrap();
+		info!("Retention policy of playlist {} set.", playlist_id.id());
+	}
+
+	fn get_retention(&self, playlist_id: &PlaylistId) -> Option<RetentionPolicy>{
+		let serialized_id = serde_json::to_string(playlist_id).unwrap();
+
+		let res = self.client.query_row(
+			"SELECT keep_last, keep_days, keep_daily, keep_weekly, keep_monthly FROM playlist_settings WHERE playlist_id = ?1",
+			params![serialized_id],
+			|row| {
+				Ok(
+					RetentionPolicy {
+						keep_last: row.get("keep_last")?,
+						keep_days: row.get("keep_days")?,
+						keep_daily: row.get("keep_daily")?,
+						keep_weekly: row.get("keep_weekly")?,
+						keep_monthly: row.get("keep_monthly")?
+					}
+				)
+			}
+		);
+
+		res.ok().filter(|p| !p.is_empty())
+	}
+
+	fn set_schedule(&self, playlist_id: &PlaylistId, schedule: Option<&str>){
+		let serialized_id = serde_json::to_string(playlist_id).unwrap();
+		self.client.execute(
+			"INSERT INTO playlist_settings (playlist_id, schedule) VALUES (?1, ?2) ON CONFLICT(playlist_id) DO UPDATE SET schedule = excluded.schedule",
+			params![serialized_id, schedule]
+		).unwrap();
+		info!("Schedule of playlist {} set to {}.", playlist_id.id(), schedule.unwrap_or("global"));
+	}
+
+	fn get_schedule(&self, playlist_id: &PlaylistId) -> Option<String>{
+		let serialized_id = serde_json::to_string(playlist_id).unwrap();
+
+		let res = self.client.query_row(
+			"SELECT schedule FROM playlist_settings WHERE playlist_id = ?1",
+			params![serialized_id],
+			|row| row.get::<_, Option<String>>("schedule")
+		);
+
+		res.ok().flatten()
+	}
+
+	fn set_paused(&self, playlist_id: &PlaylistId, paused: bool){
+		let serialized_id = serde_json::to_string(playlist_id).unwrap();
+		self.client.execute(
+			"INSERT INTO playlist_settings (playlist_id, paused) VALUES (?1, ?2) ON CONFLICT(playlist_id) DO UPDATE SET paused = excluded.paused",
+			params![serialized_id, paused]
+		).unwrap();
+		info!("Playlist {} {}.", playlist_id.id(), if paused { "paused" } else { "resumed" });
+	}
+
+	fn is_paused(&self, playlist_id: &PlaylistId) -> bool{
+		let serialized_id = serde_json::to_string(playlist_id).unwrap();
+
+		let res = self.client.query_row(
+			"SELECT paused FROM playlist_settings WHERE playlist_id = ?1",
+			params![serialized_id],
+			|row| row.get::<_, bool>("paused")
+		);
+
+		res.unwrap_or(false)
+	}
+
+	fn set_pinned(&self, playlist_id: &PlaylistId, timestamp: u64, pinned: bool){
+		let serialized_id = serde_json::to_string(playlist_id).unwrap();
+		self.client.execute(
+			"UPDATE playlists SET pinned = ?3 WHERE playlist_id = ?1 AND timestamp = ?2",
+			params![serialized_id, timestamp, pinned]
+		).unwrap();
+	}
+
+	fn get_pinned_timestamps(&self, playlist_id: &PlaylistId) -> Vec<u64>{
+		let serialized_id = serde_json::to_string(playlist_id).unwrap();
+		let mut query = self.client.prepare("SELECT timestamp FROM playlists WHERE playlist_id = ?1 AND pinned = 1").unwrap();
+		let t_iter = query.query_map([serialized_id], |row| row.get::<_, u64>("timestamp")).unwrap();
+
+		t_iter.map(|t| t.unwrap()).collect()
+	}
+
+	fn delete_version(&self, playlist_id: &PlaylistId, timestamp: u64){
+		let serialized_id = serde_json::to_string(playlist_id).unwrap();
+		let previous = self.client.query_row(
+			"SELECT * FROM playlists WHERE playlist_id = ?1 AND timestamp < ?2 ORDER BY timestamp DESC LIMIT 1",
+			params![serialized_id, timestamp],
+			|row| row_to_playlist(row, 0)
+		).ok();
+
+		for table in ["playlists", "playlist_items", "search_index", "exports"] {
+			self.client.execute(
+				&format!("DELETE FROM {} WHERE playlist_id = ?1 AND timestamp = ?2", table),
+				params![serialized_id, timestamp]
+			).unwrap();
+		}
+
+		// The following version now comes after the one preceding the deleted version
+		let next = self.client.query_row(
+			"SELECT * FROM playlists WHERE playlist_id = ?1 AND timestamp > ?2 ORDER BY timestamp ASC LIMIT 1",
+			params![serialized_id, timestamp],
+			|row| row_to_playlist(row, 0)
+		).ok();
+		if let (Some(previous), Some(next)) = (previous, next) {
+			self.client.execute(
+				"UPDATE playlists SET changes = ?3 WHERE playlist_id = ?1 AND timestamp = ?2",
+				params![serialized_id, next.timestamp, Change::format_list(&next.changes_since(&previous))]
+			).unwrap();
+		}
+		self.delete_unused_covers();
+		info!("Version {} of playlist {} deleted.", timestamp, playlist_id.id());
+	}
+
+	fn set_export_result(&self, export: &ExportResult){
+		let serialized_id = serde_json::to_string(&export.id).unwrap();
+		self.client.execute(
+			"INSERT INTO exports (playlist_id, timestamp, export_timestamp, exported_playlist_id, error) VALUES (?1, ?2, ?3, ?4, ?5)",
+			params![
+				serialized_id,
+				export.timestamp,
+				export.export_timestamp,
+				export.exported_id,
+				export.error
+			]
+		).unwrap();
+		info!("Export result of playlist {} recorded.", export.id.id());
+	}
+
+	fn get_exported_timestamps(&self, playlist_id: &PlaylistId) -> Vec<u64>{
+		let serialized_id = serde_json::to_string(playlist_id).unwrap();
+		let mut query = self.client.prepare("SELECT DISTINCT timestamp FROM exports WHERE playlist_id = ?1 AND error IS NULL").unwrap();
+		let t_iter = query.query_map([serialized_id], |row| row.get::<_, u64>("timestamp")).unwrap();
+
+		t_iter.map(|t| t.unwrap()).collect()
+	}
+
+	fn get_latest_unique_playlists(&self) -> Playlists {
+		let mut playlists = Playlists::new();
+
+		// Bare columns are taken from the row holding MAX(timestamp)
+		let mut query = self.client.prepare("SELECT *, MAX(timestamp), COUNT(playlist_id) as count FROM playlists WHERE playlist_id NOT IN (SELECT playlist_id FROM trash) GROUP BY playlist_id").unwrap();
+		let p_iter = query.query_map([], |row| row_to_playlist(row, row.get("count")?));
 
 		match p_iter {
 			Ok(ps) => {
@@ -128,32 +986,14 @@ impl Database {
 		}
 	}
 
-	pub fn get_all_tracked_versions(&self, playlist_id: &PlaylistId) -> Playlists{
+	fn get_all_tracked_versions(&self, playlist_id: &PlaylistId) -> Playlists{
 		let serialized_id = serde_json::to_string(playlist_id).unwrap();
 		let mut playlists = Playlists::new();
 		let mut count: u64 = 0;
 
-		let mut query = self.client.prepare("SELECT playlist_id, playlist_sha256, timestamp, playlist_data FROM playlists WHERE playlist_id = ?1 ORDER BY timestamp ASC").unwrap();
+		let mut query = self.client.prepare("SELECT * FROM playlists WHERE playlist_id = ?1 ORDER BY timestamp ASC").unwrap();
 		let p_iter = query.query_map([serialized_id], |row| {
-			let res = Ok(
-				Playlist {
-					id: {
-						let res: String = row.get("playlist_id").unwrap();
-						serde_json::from_str(res.as_str()).unwrap()
-					},
-					sha256: row.get("playlist_sha256").unwrap_or_else(|_| {CONF_SHA256_NULL}),
-					timestamp: row.get("timestamp").unwrap_or_else(|_| {CONF_TIMESTAMP_NULL}),
-					count: count,
-					data: {
-						let res: String = row.get("playlist_data").unwrap_or_else(|_| {CONF_NULL_STRING});
-						if !res.is_empty() {
-							serde_json::from_str(res.as_str()).unwrap()
-						} else {
-							CONF_NULL_PLAYLIST_DATA
-						}
-					}
-				}
-			);
+			let res = row_to_playlist(row, count);
 			count += 1;
 			res
 		});
@@ -171,36 +1011,374 @@ impl Database {
 		}
 	}
 
-	pub fn get_playlist_from_tracked_index(&self, playlist_id: &PlaylistId, index: u64) -> Option<Playlist>{
+	fn get_latest_tracked_versions(&self, playlist_id: &PlaylistId, limit: u64) -> Playlists{
 		let serialized_id = serde_json::to_string(playlist_id).unwrap();
+		let total: u64 = self.client.query_row("SELECT COUNT(*) FROM playlists WHERE playlist_id = ?1", [&serialized_id], |row| row.get(0)).unwrap();
+		let mut count = total;
 
-		let res = self.client.query_row("SELECT * FROM playlists WHERE playlist_id = ?1 LIMIT 1 OFFSET ?2", params![serialized_id, index], |row| {
-			Ok(
-				Playlist {
-					id: {
-						let res: String = row.get("playlist_id").unwrap();
-						serde_json::from_str(res.as_str()).unwrap()
-					},
-					sha256: row.get("playlist_sha256").unwrap_or_else(|_| {CONF_SHA256_NULL}),
-					timestamp: row.get("timestamp").unwrap_or_else(|_| {CONF_TIMESTAMP_NULL}),
-					count: index,
-					data: {
-						let res: String = row.get("playlist_data").unwrap_or_else(|_| {CONF_NULL_STRING});
-						if !res.is_empty() {
-							serde_json::from_str(res.as_str()).unwrap()
-						} else {
-							CONF_NULL_PLAYLIST_DATA
-						}
-					}
-				}		
-			)	
+		let mut query = self.client.prepare("SELECT * FROM playlists WHERE playlist_id = ?1 ORDER BY timestamp DESC LIMIT ?2").unwrap();
+		let mut playlists: Playlists = query
+			.query_map(params![serialized_id, limit], |row| {
+				count -= 1;
+				row_to_playlist(row, count)
+			})
+			.unwrap()
+			.map(|p| p.unwrap())
+			.collect();
+
+		playlists.reverse();
+		playlists
+	}
+
+	fn get_playlist_from_tracked_index(&self, playlist_id: &PlaylistId, index: u64) -> Option<Playlist>{
+		let serialized_id = serde_json::to_string(playlist_id).unwrap();
+
+		// Ordered by timestamp, the placeholder of the tracked playlist takes index 0
+		let res = self.client.query_row("SELECT playlists.*, covers.cover_data FROM playlists LEFT JOIN covers ON playlists.cover_sha256 = covers.cover_sha256 WHERE playlist_id = ?1 ORDER BY playlists.timestamp ASC LIMIT 1 OFFSET ?2", params![serialized_id, index], |row| {
+			let mut playlist = row_to_playlist(row, index)?;
+			playlist.cover = row.get("cover_data")?;
+			Ok(playlist)
 		});
 
-		match res {
-			Ok(p) => Some(p),
-			Err(_) => None	
-		}
+		res.ok()
 	}
 }
 
+fn row_to_item(row: &Row) -> rusqlite::Result<Item>{
+	Ok(
+		Item {
+			position: row.get("position")?,
+			kind: ItemKind::parse(&row.get::<_, String>("kind")?).unwrap(),
+			uri: row.get("uri")?,
+			name: row.get("name")?,
+			artists: serde_json::from_str(&row.get::<_, String>("artists")?).unwrap(),
+			album: row.get("album")?,
+			show: row.get("show")?,
+			release_date: row.get("release_date")?,
+			duration_ms: row.get("duration_ms")?,
+			added_at: row.get("added_at")?,
+			added_by: row.get("added_by")?
+		}
+	)
+}
+
+fn row_to_playlist(row: &Row, count: u64) -> rusqlite::Result<Playlist>{
+	let metadata = match row.get::<_, Option<String>>("name")? {
+		Some(name) => Some(PlaylistMetadata {
+			name,
+			description: row.get("description")?,
+			owner_id: row.get::<_, Option<String>>("owner_id")?.unwrap_or_default(),
+			public: row.get("public")?,
+			collaborative: row.get::<_, Option<bool>>("collaborative")?.unwrap_or_default(),
+			followers: row.get::<_, Option<u32>>("followers")?.unwrap_or_default()
+		}),
+		None => None
+	};
+
+	Ok(
+		Playlist {
+			id: {
+				let res: String = row.get("playlist_id").unwrap();
+				serde_json::from_str(res.as_str()).unwrap()
+			},
+			sha256: row.get("playlist_sha256").unwrap_or(CONF_SHA256_NULL),
+			tracks_sha256: row.get("tracks_sha256").unwrap_or(None),
+			cover_sha256: row.get("cover_sha256").unwrap_or(None),
+			timestamp: row.get("timestamp").unwrap_or(CONF_TIMESTAMP_NULL),
+			count,
+			metadata,
+			changes: row.get::<_, Option<String>>("changes")?.map(|c| Change::parse_list(&c)),
+			data: {
+				let res: String = row.get("playlist_data").unwrap_or(CONF_NULL_STRING);
+				if !res.is_empty() {
+					serde_json::from_str(res.as_str()).unwrap()
+				} else {
+					CONF_NULL_PLAYLIST_DATA
+				}
+			},
+			cover: None
+		}
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::memory::MemoryStorage;
+
+	use serde_json::json;
+
+	/// Both backends, so that every test checks they behave the same.
+	fn backends() -> Vec<(&'static str, Box<dyn Storage>)> {
+		vec![
+			("memory", Box::new(MemoryStorage::new())),
+			("sqlite", Box::new(SqliteStorage::open(":memory:").unwrap()))
+		]
+	}
+
+	fn playlist_id(id: &str) -> PlaylistId<'static> {
+		PlaylistId::from_id(String::from(id)).unwrap()
+	}
+
+	fn track(n: u32) -> serde_json::Value {
+		json!({
+			"album": { "album_type": "album", "artists": [], "external_urls": {}, "href": null, "id": format!("Album{:03}", n), "images": [], "name": format!("Album {}", n) },
+			"artists": [{ "external_urls": {}, "href": null, "id": "Artist01", "name": "Artist 1" }],
+			"disc_number": 1, "duration_ms": 180000, "explicit": false, "external_ids": {}, "external_urls": {}, "href": null,
+			"id": format!("Track{:05}", n), "is_local": false, "name": format!("Song {}", n), "popularity": 50, "preview_url": null, "track_number": 1
+		})
+	}
+
+	fn track_uri(n: u32) -> String {
+		format!("spotify:track:Track{:05}", n)
+	}
+
+	/// Version of `id` archived at `timestamp` holding the tracks `tracks`.
+	fn version(id: &str, name: &str, timestamp: u64, tracks: &[u32], cover: Option<&[u8]>) -> Playlist {
+		let items: Vec<serde_json::Value> = tracks.iter()
+			.map(|n| json!({ "added_at": "2024-01-01T00:00:00Z", "added_by": null, "is_local": false, "track": track(*n) }))
+			.collect();
+		let data: FullPlaylist = serde_json::from_value(json!({
+			"collaborative": false, "description": "", "external_urls": {}, "followers": { "total": 1 }, "href": "h", "id": id, "images": [],
+			"name": name, "owner": { "display_name": "owner", "external_urls": {}, "href": "h", "id": "owner", "images": [] }, "public": true,
+			"snapshot_id": timestamp.to_string(),
+			"tracks": { "href": "h", "items": items, "limit": 100, "next": null, "offset": 0, "previous": null, "total": tracks.len() }
+		})).unwrap();
+
+		Playlist {
+			id: playlist_id(id),
+			sha256: Sha256::digest(timestamp.to_be_bytes()).into(),
+			tracks_sha256: None,
+			cover_sha256: cover.map(|c| Sha256::digest(c).into()),
+			timestamp,
+			count: CONF_DEFAULT_COUNT,
+			metadata: Some(PlaylistMetadata {
+				name: String::from(name),
+				description: Some(String::new()),
+				owner_id: String::from("owner"),
+				public: Some(true),
+				collaborative: false,
+				followers: 1
+			}),
+			changes: Some(vec![Change::Tracks]),
+			data: Some(data),
+			cover: cover.map(Vec::from)
+		}
+	}
 
+	const A: &str = "37i9dQZEVXcQ9COmYvdajy";
+	const B: &str = "37i9dQZF1DXcBWIGoYBM5M";
+
+	/// `A` with two versions and covers, `B` with one.
+	fn fill(db: &dyn Storage){
+		db.set_unique_empty_playlist(&playlist_id(A));
+		db.set_playlist(&version(A, "Weekly", 100, &[1, 2], Some(b"first cover")));
+		db.set_playlist(&version(A, "Weekly", 200, &[2, 3], Some(b"second cover")));
+		db.set_unique_empty_playlist(&playlist_id(B));
+		db.set_playlist(&version(B, "Hits", 150, &[3], None));
+	}
+
+	#[test]
+	fn insert_and_latest_unique(){
+		for (name, db) in backends() {
+			fill(db.as_ref());
+			db.set_unique_empty_playlist(&playlist_id(A));
+
+			let latest: Vec<(String, u64, u64)> = db.get_latest_unique_playlists()
+				.iter()
+				.map(|p| (p.id.id().to_string(), p.timestamp, p.count))
+				.collect();
+			assert_eq!(latest, vec![(String::from(A), 200, 3), (String::from(B), 150, 2)], "{}", name);
+			assert_eq!(db.get_playlist_name(&playlist_id(A)).as_deref(), Some("Weekly"), "{}", name);
+			assert_eq!(db.get_last_check(&playlist_id(B)), 150, "{}", name);
+		}
+	}
+
+	#[test]
+	fn all_versions_and_items(){
+		for (name, db) in backends() {
+			fill(db.as_ref());
+			let a = playlist_id(A);
+
+			let versions: Vec<(u64, u64, bool)> = db.get_all_tracked_versions(&a)
+				.iter()
+				.map(|p| (p.count, p.timestamp, p.data.is_some()))
+				.collect();
+			assert_eq!(versions, vec![(0, 0, false), (1, 100, true), (2, 200, true)], "{}", name);
+			assert_eq!(db.get_version_timestamps(&a), vec![100, 200], "{}", name);
+
+			let latest = |limit| -> Vec<(u64, u64)> {
+				db.get_latest_tracked_versions(&a, limit).iter().map(|p| (p.count, p.timestamp)).collect()
+			};
+			assert_eq!(latest(1), vec![(2, 200)], "{}", name);
+			assert_eq!(latest(5), vec![(0, 0), (1, 100), (2, 200)], "{}", name);
+
+			let items: Vec<(u64, Vec<String>)> = db.get_all_items(&a)
+				.into_iter()
+				.map(|(t, items)| (t, items.into_iter().map(|i| i.uri).collect()))
+				.collect();
+			assert_eq!(items, vec![(100, vec![track_uri(1), track_uri(2)]), (200, vec![track_uri(2), track_uri(3)])], "{}", name);
+			assert_eq!(db.get_version_items(&a, 200).len(), 2, "{}", name);
+
+			let mut containing: Vec<String> = db.get_playlists_containing(&track_uri(3)).iter().map(|p| p.id().to_string()).collect();
+			containing.sort();
+			assert_eq!(containing, vec![String::from(A), String::from(B)], "{}", name);
+		}
+	}
+
+	#[test]
+	fn get_by_index(){
+		for (name, db) in backends() {
+			fill(db.as_ref());
+			let a = playlist_id(A);
+
+			let first = db.get_playlist_from_tracked_index(&a, 1).unwrap();
+			assert_eq!((first.count, first.timestamp), (1, 100), "{}", name);
+			assert_eq!(first.cover.as_deref(), Some(&b"first cover"[..]), "{}", name);
+			assert!(matches!(first.changes.as_deref(), Some([Change::Tracks])), "{}", name);
+			assert!(db.get_playlist_from_tracked_index(&a, 3).is_none(), "{}", name);
+
+			assert_eq!(db.get_version_index(&a, &Version::First), Some(1), "{}", name);
+			assert_eq!(db.get_version_index(&a, &Version::Latest), Some(2), "{}", name);
+			assert_eq!(db.get_version_index(&a, &Version::Index(3)), None, "{}", name);
+			assert_eq!(db.get_version_index(&a, &Version::At(150)), Some(1), "{}", name);
+			assert_eq!(db.get_version_index(&a, &Version::At(50)), None, "{}", name);
+			assert_eq!(db.get_version_index(&playlist_id("37i9dQZF1DX0XUsuxWHRQd"), &Version::Latest), None, "{}", name);
+		}
+	}
+
+	#[test]
+	fn delete_version(){
+		for (name, db) in backends() {
+			fill(db.as_ref());
+			let a = playlist_id(A);
+			let first_cover: [u8; 32] = Sha256::digest(b"first cover").into();
+			let second_cover: [u8; 32] = Sha256::digest(b"second cover").into();
+
+			db.set_pinned(&a, 200, true);
+			for timestamp in [100, 200] {
+				db.set_export_result(&ExportResult { id: a.clone(), timestamp, export_timestamp: 300, exported_id: Some(String::from("x")), error: None });
+			}
+			db.delete_version(&a, 100);
+
+			assert_eq!(db.get_version_timestamps(&a), vec![200], "{}", name);
+			assert_eq!(db.get_all_items(&a).len(), 1, "{}", name);
+			assert_eq!(db.get_version_index(&a, &Version::Latest), Some(1), "{}", name);
+			assert_eq!(db.get_pinned_timestamps(&a), vec![200], "{}", name);
+			assert!(db.get_cover(&first_cover).is_none(), "{}", name);
+			assert!(db.get_cover(&second_cover).is_some(), "{}", name);
+			assert_eq!(db.get_exported_timestamps(&a), vec![200], "{}", name);
+			// Now following the placeholder, which has nothing to compare with
+			let latest = db.get_playlist_from_tracked_index(&a, 1).unwrap();
+			assert!(matches!(latest.changes.as_deref(), Some([])), "{}", name);
+		}
+	}
+
+	#[test]
+	fn delete_playlist(){
+		for (name, db) in backends() {
+			fill(db.as_ref());
+			let a = playlist_id(A);
+			db.set_paused(&a, true);
+			db.set_export_result(&ExportResult { id: a.clone(), timestamp: 200, export_timestamp: 300, exported_id: Some(String::from("x")), error: None });
+			assert_eq!(db.get_exported_timestamps(&a), vec![200], "{}", name);
+
+			db.delete_playlist(&a);
+
+			let latest: Vec<String> = db.get_latest_unique_playlists().iter().map(|p| p.id.id().to_string()).collect();
+			assert_eq!(latest, vec![String::from(B)], "{}", name);
+			assert!(db.get_all_tracked_versions(&a).is_empty(), "{}", name);
+			assert!(db.get_exported_timestamps(&a).is_empty(), "{}", name);
+			assert!(!db.is_paused(&a), "{}", name);
+			assert!(db.get_cover(&Sha256::digest(b"second cover").into()).is_none(), "{}", name);
+			assert_eq!(db.get_playlists_containing(&track_uri(1)).len(), 0, "{}", name);
+		}
+	}
+
+	#[test]
+	fn trash(){
+		for (name, db) in backends() {
+			fill(db.as_ref());
+			let a = playlist_id(A);
+
+			db.trash_playlist(&a, 1000);
+			db.trash_playlist(&a, 2000);
+			db.trash_playlist(&playlist_id("37i9dQZF1DX0XUsuxWHRQd"), 1000);
+
+			let trashed: Vec<(String, u64, Option<String>, u64)> = db.get_trashed_playlists()
+				.into_iter()
+				.map(|t| (t.id.id().to_string(), t.deleted_at, t.name, t.versions))
+				.collect();
+			assert_eq!(trashed, vec![(String::from(A), 1000, Some(String::from("Weekly")), 2)], "{}", name);
+			assert_eq!(db.get_latest_unique_playlists().len(), 1, "{}", name);
+			assert_eq!(db.get_version_timestamps(&a), vec![100, 200], "{}", name);
+
+			// Tracking a trashed playlist again restores it
+			db.set_unique_empty_playlist(&a);
+			assert!(db.get_trashed_playlists().is_empty(), "{}", name);
+			assert_eq!(db.get_latest_unique_playlists().len(), 2, "{}", name);
+			assert!(!db.restore_playlist(&a), "{}", name);
+
+			db.trash_playlist(&a, 3000);
+			assert!(db.restore_playlist(&a), "{}", name);
+			assert_eq!(db.get_all_tracked_versions(&a).len(), 3, "{}", name);
+		}
+	}
+
+	#[test]
+	fn settings(){
+		for (name, db) in backends() {
+			fill(db.as_ref());
+			let a = playlist_id(A);
+
+			db.set_auto_export(&a, true, Some("{name}"));
+			db.set_auto_export(&a, true, None);
+			assert_eq!(db.get_auto_export(&a).as_deref(), Some("{name}"), "{}", name);
+			db.set_auto_export(&a, false, None);
+			assert_eq!(db.get_auto_export(&a), None, "{}", name);
+
+			db.set_schedule(&a, Some("7d"));
+			assert_eq!(db.get_schedule(&a).as_deref(), Some("7d"), "{}", name);
+			db.set_schedule(&a, None);
+			assert_eq!(db.get_schedule(&a), None, "{}", name);
+
+			assert!(db.get_retention(&a).is_none(), "{}", name);
+			db.set_retention(&a, Some(&RetentionPolicy { keep_last: Some(3), ..RetentionPolicy::default() }));
+			assert_eq!(db.get_retention(&a).and_then(|p| p.keep_last), Some(3), "{}", name);
+		}
+	}
+
+	#[test]
+	fn unchanged_legacy_version(){
+		// Database as created before covers, metadata and the hash of the items were recorded
+		let path = std::env::temp_dir().join(format!("archify-legacy-{}.sqlite", std::process::id()));
+		let path = path.to_str().unwrap();
+		let tracks_sha256: [u8; 32] = Sha256::digest(b"Track00001Track00002").into();
+		let mut legacy = version(A, "Weekly", 100, &[1, 2], None);
+		legacy.data.as_mut().unwrap().images = serde_json::from_value(json!([{ "height": null, "url": "u", "width": null }])).unwrap();
+		{
+			let client = Connection::open(path).unwrap();
+			client.execute("CREATE TABLE IF NOT EXISTS playlists (playlist_id TEXT, playlist_SHA256 BLOB, timestamp TIMESTAMP, playlist_data TEXT, PRIMARY KEY (playlist_id, timestamp))", ()).unwrap();
+			let serialized_id = serde_json::to_string(&playlist_id(A)).unwrap();
+			client.execute("INSERT INTO playlists (playlist_id, timestamp, playlist_data) VALUES (?1, ?2, ?3)", params![serialized_id, CONF_TIME_BIG_BANG, ""]).unwrap();
+			client.execute(
+				"INSERT INTO playlists (playlist_id, playlist_SHA256, timestamp, playlist_data) VALUES (?1, ?2, ?3, ?4)",
+				params![serialized_id, tracks_sha256, 100, serde_json::to_string(&legacy.data).unwrap()]
+			).unwrap();
+		}
+
+		let db = SqliteStorage::open(path).unwrap();
+		let latest = db.get_latest_unique_playlists().pop().unwrap();
+		std::fs::remove_file(path).unwrap();
+
+		// Same items, with the cover and followers now known
+		let mut fresh = version(A, "Weekly", 200, &[1, 2], Some(b"cover"));
+		fresh.tracks_sha256 = Some(tracks_sha256);
+		fresh.metadata.as_mut().unwrap().followers = 2;
+		assert!(fresh.changes_since(&latest).iter().all(|c| matches!(c, Change::Followers)));
+		assert!(!fresh.is_new_version_of(&latest));
+
+		fresh.metadata.as_mut().unwrap().name = String::from("Renamed");
+		assert!(fresh.is_new_version_of(&latest));
+	}
+}
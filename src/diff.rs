@@ -0,0 +1,48 @@
+use crate::items::Item;
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct Diff {
+	pub added: Vec<Item>,
+	pub removed: Vec<Item>
+}
+
+impl Diff {
+	pub fn is_empty(&self) -> bool {
+		self.added.is_empty() && self.removed.is_empty()
+	}
+}
+
+/// Items only present in `new` are added, items only present in `old` are removed. Duplicates are matched one to one.
+pub fn diff(old: &[Item], new: &[Item]) -> Diff {
+	let mut remaining: HashMap<&str, usize> = HashMap::new();
+	for item in old {
+		*remaining.entry(item.uri.as_str()).or_default() += 1;
+	}
+
+	let mut added = Vec::new();
+	for item in new {
+		match remaining.get_mut(item.uri.as_str()) {
+			Some(count) if *count > 0 => *count -= 1,
+			_ => added.push(item.clone())
+		}
+	}
+
+	let mut kept: HashMap<&str, usize> = HashMap::new();
+	for item in new {
+		*kept.entry(item.uri.as_str()).or_default() += 1;
+	}
+
+	let mut removed = Vec::new();
+	for item in old {
+		match kept.get_mut(item.uri.as_str()) {
+			Some(count) if *count > 0 => *count -= 1,
+			_ => removed.push(item.clone())
+		}
+	}
+
+	Diff { added, removed }
+}
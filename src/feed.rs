@@ -0,0 +1,271 @@
+use crate::conf::*;
+use crate::items::{self, Item};
+use crate::web::escape;
+use crate::{database, diff, notify};
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use rspotify::model::PlaylistId;
+use rspotify::prelude::Id;
+
+#[derive(Clone, Copy)]
+pub enum FeedFormat {
+	Atom,
+	Rss
+}
+
+impl FeedFormat {
+	pub fn extension(&self) -> &'static str {
+		match self {
+			FeedFormat::Atom => "atom",
+			FeedFormat::Rss => "rss"
+		}
+	}
+
+	pub fn content_type(&self) -> &'static str {
+		match self {
+			FeedFormat::Atom => "application/atom+xml",
+			FeedFormat::Rss => "application/rss+xml"
+		}
+	}
+
+	pub fn from_extension(extension: &str) -> Option<FeedFormat> {
+		match extension {
+			"atom" => Some(FeedFormat::Atom),
+			"rss" => Some(FeedFormat::Rss),
+			_ => None
+		}
+	}
+}
+
+/// A new version of a playlist, with what it added and removed.
+pub struct Entry {
+	pub playlist_id: String,
+	pub playlist_name: String,
+	pub index: u64,
+	pub timestamp: u64,
+	pub added: Vec<Item>,
+	pub removed: Vec<Item>
+}
+
+pub struct Feed {
+	pub id: String,
+	pub title: String,
+	pub link: String,
+	/// Newest first.
+	pub entries: Vec<Entry>
+}
+
+fn to_date(timestamp: u64) -> DateTime<Utc> {
+	DateTime::<Utc>::from(UNIX_EPOCH + Duration::from_secs(timestamp))
+}
+
+fn playlist_link(playlist_id: &str) -> String {
+	format!("https://open.spotify.com/playlist/{}", playlist_id)
+}
+
+/// Latest versions of a playlist as entries, newest first.
+fn get_entries(db: &dyn database::Storage, playlist_id: &PlaylistId) -> Vec<Entry> {
+	// One version more than the entries, which the oldest entry is compared to: the placeholder for the first version
+	let versions = db.get_latest_tracked_versions(playlist_id, CONF_FEED_MAX_ENTRIES as u64 + 1);
+	let items: Vec<Vec<Item>> = versions.iter()
+		.map(|p| p.data.as_ref().map(items::get_items).unwrap_or_default())
+		.collect();
+
+	let mut entries = Vec::new();
+	for (i, p) in versions.iter().enumerate().skip(1) {
+		let d = diff::diff(&items[i - 1], &items[i]);
+		entries.push(Entry {
+			playlist_id: playlist_id.id().to_string(),
+			playlist_name: p.data.as_ref().map(|d| d.name.clone()).unwrap_or_else(|| playlist_id.id().to_string()),
+			index: p.count,
+			timestamp: p.timestamp,
+			added: d.added,
+			removed: d.removed
+		});
+	}
+
+	entries.reverse();
+	entries
+}
+
+fn playlist_feed(playlist_id: &PlaylistId, entries: Vec<Entry>) -> Option<Feed> {
+	let name = entries.first()?.playlist_name.clone();
+
+	Some(Feed {
+		id: format!("urn:archify:playlist:{}", playlist_id.id()),
+		title: format!("Archify - {}", name),
+		link: playlist_link(playlist_id.id()),
+		entries
+	})
+}
+
+pub fn get_playlist_feed(db: &dyn database::Storage, playlist_id: &PlaylistId) -> Option<Feed> {
+	playlist_feed(playlist_id, get_entries(db, playlist_id))
+}
+
+fn global_feed(mut entries: Vec<Entry>) -> Feed {
+	entries.sort_by_key(|e| std::cmp::Reverse(e.timestamp));
+	entries.truncate(CONF_FEED_MAX_ENTRIES);
+
+	Feed {
+		id: String::from("urn:archify:playlists"),
+		title: String::from("Archify - All playlists"),
+		link: String::from("https://open.spotify.com"),
+		entries
+	}
+}
+
+pub fn get_global_feed(db: &dyn database::Storage) -> Feed {
+	global_feed(db.get_latest_unique_playlists().iter().flat_map(|p| get_entries(db, &p.id)).collect())
+}
+
+fn entry_title(entry: &Entry) -> String {
+	match entry.index {
+		1 => format!("{} - first version, {} item(s)", entry.playlist_name, entry.added.len()),
+		_ => format!("{} - version {}: {} added, {} removed", entry.playlist_name, entry.index, entry.added.len(), entry.removed.len())
+	}
+}
+
+/// HTML listing the added and removed items, escaped once more by the caller to fit in the XML.
+fn entry_content(entry: &Entry) -> String {
+	let list = |items: &[Item]| -> String {
+		items.iter()
+			.map(|i| format!("<li>{}{} - {}</li>", escape(i.marker()), escape(&i.name), escape(&i.creator())))
+			.collect()
+	};
+
+	let mut content = String::new();
+	if !entry.added.is_empty() {
+		content.push_str(&format!("<h3>Added</h3><ul>{}</ul>", list(&entry.added)));
+	}
+	if !entry.removed.is_empty() {
+		content.push_str(&format!("<h3>Removed</h3><ul>{}</ul>", list(&entry.removed)));
+	}
+	if content.is_empty() {
+		content.push_str("<p>No item added or removed.</p>");
+	}
+	content
+}
+
+fn render_atom(feed: &Feed) -> String {
+	let updated = feed.entries.first().map(|e| e.timestamp).unwrap_or(CONF_TIMESTAMP_NULL);
+	let mut xml = format!(
+		"<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n<id>{}</id>\n<title>{}</title>\n<link href=\"{}\"/>\n<updated>{}</updated>\n<author><name>Archify</name></author>\n",
+		escape(&feed.id), escape(&feed.title), escape(&feed.link), to_date(updated).to_rfc3339()
+	);
+	for entry in &feed.entries {
+		xml.push_str(&format!(
+			"<entry>\n<id>urn:archify:playlist:{}:{}</id>\n<title>{}</title>\n<link href=\"{}\"/>\n<updated>{}</updated>\n<content type=\"html\">{}</content>\n</entry>\n",
+			escape(&entry.playlist_id),
+			entry.timestamp,
+			escape(&entry_title(entry)),
+			escape(&playlist_link(&entry.playlist_id)),
+			to_date(entry.timestamp).to_rfc3339(),
+			escape(&entry_content(entry))
+		));
+	}
+	xml.push_str("</feed>\n");
+	xml
+}
+
+fn render_rss(feed: &Feed) -> String {
+	let mut xml = format!(
+		"<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<rss version=\"2.0\">\n<channel>\n<title>{}</title>\n<link>{}</link>\n<description>{}</description>\n",
+		escape(&feed.title), escape(&feed.link), escape(&feed.title)
+	);
+	if let Some(entry) = feed.entries.first() {
+		xml.push_str(&format!("<lastBuildDate>{}</lastBuildDate>\n", to_date(entry.timestamp).to_rfc2822()));
+	}
+	for entry in &feed.entries {
+		xml.push_str(&format!(
+			"<item>\n<guid isPermaLink=\"false\">urn:archify:playlist:{}:{}</guid>\n<title>{}</title>\n<link>{}</link>\n<pubDate>{}</pubDate>\n<description>{}</description>\n</item>\n",
+			escape(&entry.playlist_id),
+			entry.timestamp,
+			escape(&entry_title(entry)),
+			escape(&playlist_link(&entry.playlist_id)),
+			to_date(entry.timestamp).to_rfc2822(),
+			escape(&entry_content(entry))
+		));
+	}
+	xml.push_str("</channel>\n</rss>\n");
+	xml
+}
+
+pub fn render(feed: &Feed, format: FeedFormat) -> String {
+	match format {
+		FeedFormat::Atom => render_atom(feed),
+		FeedFormat::Rss => render_rss(feed)
+	}
+}
+
+/// Feed files in `dir` of playlists not tracked anymore, e.g. trashed or deleted.
+fn stale_feeds(db: &dyn database::Storage, dir: &str) -> Vec<PathBuf> {
+	let tracked: HashSet<String> = db.get_latest_unique_playlists().iter().map(|p| p.id.id().to_string()).collect();
+	let files = match fs::read_dir(dir) {
+		Ok(files) => files,
+		Err(_) => return Vec::new()
+	};
+
+	files.filter_map(|f| f.ok())
+		.map(|f| f.path())
+		.filter(|path| {
+			let extension = path.extension().and_then(|e| e.to_str()).and_then(FeedFormat::from_extension);
+			let name = path.file_stem().and_then(|n| n.to_str()).unwrap_or_default();
+			extension.is_some() && name != CONF_FEED_GLOBAL_NAME && PlaylistId::id_is_valid(name) && !tracked.contains(name)
+		})
+		.collect()
+}
+
+fn write_feed(dir: &str, name: &str, feed: &Feed){
+	for format in [FeedFormat::Atom, FeedFormat::Rss] {
+		let path = Path::new(dir).join(format!("{}.{}", name, format.extension()));
+		if let Err(e) = fs::write(&path, render(feed, format)) {
+			error!("Feed {} cannot be written: {}", path.display(), e);
+		}
+	}
+}
+
+/// Writes `all.atom`, `all.rss` and the `<playlist id>.atom` and `.rss` feeds of every tracked playlist into `dir`,
+/// and removes the feeds of the playlists not tracked anymore.
+pub fn write(db: &dyn database::Storage, dir: &str){
+	if let Err(e) = fs::create_dir_all(dir) {
+		error!("Feed directory {} cannot be created: {}", dir, e);
+		return;
+	}
+
+	// The entries of every playlist are computed once, for its feed then for the global one
+	let mut all_entries = Vec::new();
+	let mut written = 1;
+	for p in db.get_latest_unique_playlists() {
+		if let Some(feed) = playlist_feed(&p.id, get_entries(db, &p.id)) {
+			write_feed(dir, p.id.id(), &feed);
+			all_entries.extend(feed.entries);
+			written += 1;
+		}
+	}
+	write_feed(dir, CONF_FEED_GLOBAL_NAME, &global_feed(all_entries));
+
+	for path in stale_feeds(db, dir) {
+		match fs::remove_file(&path) {
+			Ok(_) => info!("Feed {} removed.", path.display()),
+			Err(e) => error!("Feed {} cannot be removed: {}", path.display(), e)
+		}
+	}
+	info!("{} feed(s) written to {}.", written, dir);
+}
+
+/// Writes the feeds after an update, if it archived a new version, they were never written or some playlists are not
+/// tracked anymore.
+pub fn update(db: &dyn database::Storage, dir: &str, events: &[notify::Event]){
+	let new_version = events.iter().any(|e| matches!(e, notify::Event::NewVersion { .. }));
+	let missing = !Path::new(dir).join(format!("{}.{}", CONF_FEED_GLOBAL_NAME, FeedFormat::Atom.extension())).exists();
+	if new_version || missing || !stale_feeds(db, dir).is_empty() {
+		write(db, dir);
+	}
+}
@@ -0,0 +1,59 @@
+use crate::items::Item;
+
+use serde::Serialize;
+
+/// Consecutive versions of a playlist containing an item.
+#[derive(Serialize)]
+pub struct Range {
+	pub first_index: u64,
+	pub first_timestamp: u64,
+	pub last_index: u64,
+	pub last_timestamp: u64,
+	/// 1-based positions the item took within the range.
+	pub positions: Vec<usize>
+}
+
+#[derive(Serialize)]
+pub struct PlaylistHistory {
+	pub playlist_id: String,
+	pub playlist_name: Option<String>,
+	pub ranges: Vec<Range>
+}
+
+/// Splits the versions of a playlist, ascending by timestamp, into the ranges where `uri` is present.
+pub fn get_ranges(versions: &[(u64, Vec<Item>)], uri: &str) -> Vec<Range> {
+	let mut ranges: Vec<Range> = Vec::new();
+	let mut in_range = false;
+
+	for (i, (timestamp, items)) in versions.iter().enumerate(){
+		let index = i as u64 + 1;
+		let positions: Vec<usize> = items.iter().filter(|item| item.uri == uri).map(|item| item.position + 1).collect();
+
+		if positions.is_empty() {
+			in_range = false;
+			continue;
+		}
+
+		match ranges.last_mut() {
+			Some(range) if in_range => {
+				range.last_index = index;
+				range.last_timestamp = *timestamp;
+				for p in positions {
+					if !range.positions.contains(&p) {
+						range.positions.push(p);
+					}
+				}
+			},
+			_ => ranges.push(Range {
+				first_index: index,
+				first_timestamp: *timestamp,
+				last_index: index,
+				last_timestamp: *timestamp,
+				positions
+			})
+		}
+		in_range = true;
+	}
+
+	ranges
+}
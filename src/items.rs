@@ -0,0 +1,183 @@
+use std::io::{self, Write};
+
+use rspotify::model::{FullPlaylist, FullTrack, PlayableItem};
+use rspotify::prelude::Id;
+use serde::Serialize;
+use url::form_urlencoded::byte_serialize;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemKind {
+	Track,
+	Episode,
+	/// File from the owner's computer, which cannot be added back through the API.
+	Local
+}
+
+impl ItemKind {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			ItemKind::Track => "track",
+			ItemKind::Episode => "episode",
+			ItemKind::Local => "local"
+		}
+	}
+
+	pub fn parse(kind: &str) -> Option<ItemKind> {
+		match kind {
+			"track" => Some(ItemKind::Track),
+			"episode" => Some(ItemKind::Episode),
+			"local" => Some(ItemKind::Local),
+			_ => None
+		}
+	}
+}
+
+/// Flattened view of a playlist entry, whatever its kind.
+#[derive(Clone, Serialize)]
+pub struct Item {
+	pub position: usize,
+	pub kind: ItemKind,
+	pub uri: String,
+	pub name: String,
+	/// Artists of a track, empty for an episode.
+	pub artists: Vec<String>,
+	pub album: Option<String>,
+	/// Show of an episode.
+	pub show: Option<String>,
+	pub release_date: Option<String>,
+	pub duration_ms: u64,
+	/// RFC 3339 date at which the item was added to the playlist.
+	pub added_at: Option<String>,
+	/// Id of the user who added the item.
+	pub added_by: Option<String>
+}
+
+impl Item {
+	/// Artists of a track or show of an episode.
+	pub fn creator(&self) -> String {
+		match self.kind {
+			ItemKind::Episode => self.show.clone().unwrap_or_default(),
+			_ => self.artists.join(", ")
+		}
+	}
+
+	/// Marker to display next to the item name.
+	pub fn marker(&self) -> &'static str {
+		match self.kind {
+			ItemKind::Track => "",
+			ItemKind::Episode => "[episode] ",
+			ItemKind::Local => "[local] "
+		}
+	}
+
+	pub fn format_duration(&self) -> String {
+		let seconds = self.duration_ms / 1000;
+		format!("{}:{:02}", seconds / 60, seconds % 60)
+	}
+}
+
+fn encode(s: &str) -> String {
+	byte_serialize(s.as_bytes()).collect()
+}
+
+/// Rebuilds the `spotify:local:artist:album:name:seconds` URI that the API drops from local tracks.
+pub fn local_uri(track: &FullTrack) -> String {
+	format!(
+		"spotify:local:{}:{}:{}:{}",
+		encode(&track.artists.iter().map(|a| a.name.as_str()).collect::<Vec<_>>().join(", ")),
+		encode(&track.album.name),
+		encode(&track.name),
+		track.duration.num_seconds()
+	)
+}
+
+/// Identifies a local track from everything the API tells about it.
+pub fn local_fingerprint(track: &FullTrack) -> String {
+	format!(
+		"{}\0{}\0{}\0{}\0{}",
+		local_uri(track),
+		track.name,
+		track.artists.iter().map(|a| a.name.as_str()).collect::<Vec<_>>().join(", "),
+		track.album.name,
+		track.duration.num_milliseconds()
+	)
+}
+
+pub fn get_items(data: &FullPlaylist) -> Vec<Item> {
+	let mut items = Vec::new();
+
+	for (position, item) in data.tracks.items.iter().enumerate(){
+		match &item.track {
+			Some(PlayableItem::Track(track)) => {
+				let (kind, uri) = match &track.id {
+					Some(id) => (ItemKind::Track, id.uri()),
+					None => (ItemKind::Local, local_uri(track))
+				};
+				items.push(Item {
+					position,
+					kind,
+					uri,
+					name: track.name.clone(),
+					artists: track.artists.iter().map(|a| a.name.clone()).collect(),
+					album: Some(track.album.name.clone()),
+					show: None,
+					release_date: track.album.release_date.clone(),
+					duration_ms: track.duration.num_milliseconds() as u64,
+					added_at: item.added_at.map(|d| d.to_rfc3339()),
+					added_by: item.added_by.as_ref().map(|u| u.id.id().to_string())
+				});
+			},
+			Some(PlayableItem::Episode(episode)) => {
+				items.push(Item {
+					position,
+					kind: ItemKind::Episode,
+					uri: episode.id.uri(),
+					name: episode.name.clone(),
+					artists: Vec::new(),
+					album: None,
+					show: Some(episode.show.name.clone()),
+					release_date: Some(episode.release_date.clone()),
+					duration_ms: episode.duration.num_milliseconds() as u64,
+					added_at: item.added_at.map(|d| d.to_rfc3339()),
+					added_by: item.added_by.as_ref().map(|u| u.id.id().to_string())
+				});
+			},
+			None => ()
+		}
+	}
+
+	items
+}
+
+fn csv_field(field: &str) -> String {
+	if field.contains([',', '"', '\n']) {
+		format!("\"{}\"", field.replace('"', "\"\""))
+	}else{
+		String::from(field)
+	}
+}
+
+pub fn write_csv(items: &[Item], writer: &mut impl Write) -> io::Result<()> {
+	writeln!(writer, "position,kind,name,creator,album,release_date,duration_ms,uri,note")?;
+	for item in items {
+		let note = match item.kind {
+			ItemKind::Local => "local file, cannot be exported to Spotify",
+			_ => ""
+		};
+		writeln!(
+			writer,
+			"{},{},{},{},{},{},{},{},{}",
+			item.position + 1,
+			item.kind.as_str(),
+			csv_field(&item.name),
+			csv_field(&item.creator()),
+			csv_field(item.album.as_deref().unwrap_or_default()),
+			item.release_date.as_deref().unwrap_or_default(),
+			item.duration_ms,
+			csv_field(&item.uri),
+			csv_field(note)
+		)?;
+	}
+	Ok(())
+}
@@ -0,0 +1,84 @@
+//! Archify keeps a record of Spotify playlists, such as Discover Weekly, whose content changes over time.
+//!
+//! Every update fetches the tracked playlists and stores a new version of those that changed in a SQLite database.
+//! [`Archive`] is the entry point: it tracks playlists, updates them, lists their versions and exports them back to
+//! Spotify. It stores them in a [`Storage`]: [`SqliteStorage`], or [`MemoryStorage`] to keep nothing on disk.
+//!
+//! The `cli` feature, enabled by default, builds the `archify` binary. Disable default features to depend on the
+//! library alone.
+//!
+//! ```no_run
+//! use archify::{Archive, ArchifyConf, Version};
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let mut archive = Archive::open(ArchifyConf::new("client id", "client secret")).unwrap();
+//!     let id = archive.track("https://open.spotify.com/playlist/37i9dQZEVXcQ9COmYvdajy").await.unwrap();
+//!     archive.update(false).await.unwrap();
+//!
+//!     for version in archive.versions(&id).await {
+//!         println!("{}: {:?}", version.index, version.changes);
+//!     }
+//!     if let Err(e) = archive.export(&id, &Version::Latest, "{name} - {date}").await {
+//!         println!("{}", e);
+//!     }
+//! }
+//! ```
+
+// Without the CLI, the helpers it shares with the library are left unused
+#![cfg_attr(not(feature = "cli"), allow(dead_code, unused_imports))]
+
+// Modules of the library, public for the `archify` CLI only: they are not part of the API of the library, whose
+// types are re-exported below
+macro_rules! cli_pub_mod {
+	($($name:ident),*) => {$(
+		#[cfg(feature = "cli")]
+		#[doc(hidden)]
+		pub mod $name;
+		#[cfg(not(feature = "cli"))]
+		mod $name;
+	)*}
+}
+
+cli_pub_mod!(archive, conf, database, diff, items, notify, retention, schedule, spotify);
+mod cover;
+mod feed;
+mod memory;
+mod metrics;
+mod web;
+
+// Modules of the `archify` CLI only
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod compare;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod daemon;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod history;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod report;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod search;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod server;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod site;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod stats;
+
+pub use archive::{get_version, parse_playlist_id, Archive, ArchifyConf, PlaylistSummary, VersionSummary};
+pub use database::{Change, ExportResult, Observation, Playlist, PlaylistMetadata, Playlists, SearchHit, SqliteStorage, Storage, TrashEntry, Version};
+pub use items::Item;
+pub use memory::MemoryStorage;
+pub use notify::{Event, Sink, SmtpSink};
+pub use retention::RetentionPolicy;
+// Types of rspotify, such as `PlaylistId`, `FullPlaylist` and `AuthCodeSpotify`, are part of the API: using them from
+// this re-export keeps their version the one of archify
+pub use rspotify;
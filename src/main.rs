@@ -1,19 +1,16 @@
 mod arguments;
-mod conf;
-mod database;
-mod spotify;
 
-use conf::*;
-use rspotify::model::PlaylistId;
+use archify::conf::*;
+use archify::{archive, compare, daemon, database, diff, history, items, notify, report, retention, search, server, site, spotify, stats};
+use archify::{Archive, ArchifyConf};
+use rspotify::model::{PlaylistId, TrackId};
 use rspotify::prelude::Id;
-use spotify::get_spotify_client_from_client_credentials;
 
 use std::env;
-use std::str::FromStr;
 use std::fs::File;
 use std::io::{Read, Write};
 use std::process::exit;
-use std::time::{UNIX_EPOCH, Duration};
+use std::time::{SystemTime, UNIX_EPOCH, Duration};
 
 use chrono::{Local, DateTime};
 use env_logger::Builder;
@@ -24,11 +21,6 @@ use single_instance::SingleInstance;
 use tokio::runtime::Runtime;
 use url::Url;
 
-pub struct ArchifyConf{
-	archify_id: String,
-	archify_secret: String
-}
-
 fn extract_configuration() -> ArchifyConf{
 	let mut buf = String::new();
 
@@ -39,27 +31,23 @@ fn extract_configuration() -> ArchifyConf{
 
 	let json_api: Value = serde_json::from_str(&*buf).unwrap();
 
-	if json_api[CONF_ARCHIFY_ID].is_null() || json_api[CONF_ARCHIFY_SECRET].is_null(){
-		error!("Configuration file cannot be parsed correctly!");
-		exit(-1);
-	}
-
-	ArchifyConf { 
-		archify_id: String::from_str(json_api[CONF_ARCHIFY_ID].as_str().unwrap()).unwrap(), 
-		archify_secret: String::from_str(json_api[CONF_ARCHIFY_SECRET].as_str().unwrap()).unwrap() 
+	match ArchifyConf::from_json(&json_api) {
+		Ok(conf) => conf,
+		Err(e) => {
+			error!("Configuration file cannot be parsed correctly! {}", e);
+			exit(-1);
+		}
 	}
-
 }
 
 fn verify_single_instance() -> SingleInstance{
-	let instance = SingleInstance::new("archify").unwrap();
-
-	if !instance.is_single(){
-		error!("Only one instance of archify must run at the same time!");
-		exit(-1);
+	match archive::lock_updates() {
+		Some(instance) => instance,
+		None => {
+			error!("Only one instance of archify must update the playlists at the same time!");
+			exit(-1);
+		}
 	}
-
-	instance
 }
 
 fn parse_url(url: &String) -> Option<String>{
@@ -74,97 +62,500 @@ fn parse_url(url: &String) -> Option<String>{
 
 }
 
-fn add_playlist(db: &database::Database, playlist_ids: Vec<String>){
+async fn add_playlist(archive: &Archive, playlist_ids: Vec<String>){
 	for p in playlist_ids{
-		match parse_url(&p){
-			Some(p_url) => db.set_unique_empty_playlist(&PlaylistId::from_id(p_url).unwrap()),
-			None => ()
+		if let Err(e) = archive.track(&p).await {
+			error!("{}", e);
 		}
 	}
 }
 
-fn delete_playlist(db: &database::Database, playlist_ids: Vec<String>){
+async fn delete_playlist(archive: &Archive, args: arguments::DeleteArgs){
+	for p in args.playlist_ids{
+		if let Err(e) = archive.untrack(&p, args.hard).await {
+			error!("{}", e);
+		}
+	}
+}
+
+fn list_trash(db: &dyn database::Storage){
+	let entries = db.get_trashed_playlists();
+	println!("List of trashed playlist:");
+	for t in entries {
+		let date = DateTime::<Local>::from(UNIX_EPOCH + Duration::from_secs(t.deleted_at));
+		println!(
+			"[{}]: {} ({} version(s)) - deleted {}",
+			t.id.id(), t.name.unwrap_or_else(|| String::from("! Name not available !")), t.versions, date.format("%v %X")
+		);
+	}
+}
+
+fn restore_trash(db: &dyn database::Storage, playlist_ids: Vec<String>){
 	for p in playlist_ids{
-		match parse_url(&p){
-			Some(p_url) => db.delete_playlist(&PlaylistId::from_id(p_url).unwrap()),
-			None => ()
+		let p_id = get_playlist_id(&p);
+		if !db.restore_playlist(&p_id){
+			error!("Playlist {} is not in the trash.", p_id.id());
 		}
 	}
 }
 
-async fn update_playlists(db: &database::Database, conf: &ArchifyConf){
-	let playlists = db.get_latest_unique_playlists();
-	let client = get_spotify_client_from_client_credentials(&conf).await;
+fn purge_trash(db: &dyn database::Storage, older_than: Option<u64>){
+	let now = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.unwrap()
+		.as_secs();
+	let limit = now.saturating_sub(older_than.unwrap_or_default() * CONF_SECONDS_PER_DAY);
 
-	for p in playlists{
-		let fresh_p = spotify::get_public_playlists(&client, &p.id).await;
+	for t in db.get_trashed_playlists(){
+		if t.deleted_at <= limit {
+			db.delete_playlist(&t.id);
+		}
+	}
+}
+
+fn get_playlist_id(playlist_id: &String) -> PlaylistId<'static>{
+	if !PlaylistId::id_is_valid(playlist_id.as_str()){
+		PlaylistId::from_id(parse_url(playlist_id).unwrap()).unwrap()
+	}else{
+		PlaylistId::from_id(playlist_id.clone()).unwrap()
+	}
+}
 
-		if p.sha256 != fresh_p.sha256{
-			db.set_playlist(&fresh_p);
-		}else{
-			info!("Playlist {} SHA matching, not pushed to db.", p.id.id());
+fn set_auto_export(db: &dyn database::Storage, args: arguments::AutoExportArgs){
+	db.set_auto_export(&get_playlist_id(&args.playlist_id), args.enabled, args.template.as_deref());
+}
+
+async fn test_notifications(conf: &ArchifyConf){
+	if conf.notifications.is_empty(){
+		error!("No notification sink configured in config.json.");
+		return;
+	}
+	let timestamp = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.unwrap()
+		.as_secs();
+	let event = notify::Event::NewVersion {
+		playlist_id: String::from("37i9dQZEVXcQ9COmYvdajy"),
+		playlist_name: String::from("Archify test notification"),
+		timestamp,
+		index: 1,
+		changes: vec![String::from(database::Change::Tracks.as_str())],
+		added: 1,
+		removed: 0
+	};
+	notify::send(&conf.notifications, &[event]).await;
+}
+
+fn format_next_due(next_due: Option<u64>, paused: bool) -> String{
+	match next_due {
+		_ if paused => String::from("paused"),
+		None => String::from("no next update"),
+		Some(due) => {
+			let date = DateTime::<Local>::from(UNIX_EPOCH + Duration::from_secs(due));
+			format!("next update {}", date.format("%v %X"))
 		}
 	}
 }
 
-async fn list_playlists(db: &database::Database){
-	let playlists = db.get_latest_unique_playlists();
+fn pause_playlists(db: &dyn database::Storage, playlist_ids: Vec<String>, paused: bool){
+	for p in playlist_ids{
+		db.set_paused(&get_playlist_id(&p), paused);
+	}
+}
+
+fn set_schedule(archive: &Archive, args: arguments::ScheduleArgs){
+	let db = archive.db();
+	let p_id = get_playlist_id(&args.playlist_id);
+
+	if args.clear {
+		db.set_schedule(&p_id, None);
+	}else if let Some(spec) = &args.schedule {
+		db.set_schedule(&p_id, Some(spec));
+	}
+
+	let spec = archive.schedule(&p_id).unwrap_or_else(|| String::from("no schedule, updated at every --update"));
+	println!("[{}]: {} - {}", p_id.id(), spec, format_next_due(archive.next_due(&p_id), db.is_paused(&p_id)));
+}
+
+async fn list_playlists(archive: &Archive){
+	let playlists = archive.list().await;
 	println!("List of tracked playlist:");
 	for p in playlists{
-		match p.data {
-			Some(data) => println!("[{}]: {} ({} version(s))", p.id.id(), data.name, p.count - 1),
-			None => println!("[{}]: ! Name not available, please --update first !", p.id.id())
+		let next_due = format_next_due(p.next_due, p.paused);
+		match p.name {
+			Some(name) => println!("[{}]: {} ({} version(s)) - {}", p.id, name, p.versions, next_due),
+			None => println!("[{}]: ! Name not available, please --update first ! - {}", p.id, next_due)
 		}
 	}
 }
 
-fn list_tracked_versions(db: &database::Database, playlist_id: &String){
-	let p_id ;
-	if !PlaylistId::id_is_valid(playlist_id.as_str()){
-		p_id = PlaylistId::from_id(parse_url(playlist_id).unwrap()).unwrap();
-	}else{
-		p_id = PlaylistId::from_id(playlist_id).unwrap();
-	}
+async fn list_tracked_versions(archive: &Archive, playlist_id: &String){
+	let p_id = get_playlist_id(playlist_id);
 
-	let playlists = db.get_all_tracked_versions(&p_id);
+	let versions = archive.versions(&p_id).await;
 
-	if playlists.is_empty(){
+	if archive.db().get_all_tracked_versions(&p_id).is_empty(){
 		error!("No playlist with this id are recorded!");
-	}else if playlists.len() == 1 {
+	}else if versions.is_empty() {
 		error!("Playlist has not been updated yet! Do an --update first.")
 	}else{
-		println!("List of tracked versions for [{}] - {}:", p_id.id(), playlists.get(1).unwrap().data.as_ref().unwrap().name);
-		for p in playlists{
-			if p.count != 0{
-				let date = DateTime::<Local>::from(UNIX_EPOCH + Duration::from_secs(p.timestamp));
-				let format_date = format!("{}", date.format("%v %X"));	
-				println!("[{}]: {}", p.count, format_date);
+		println!("List of tracked versions for [{}] - {}:", p_id.id(), versions[0].name.as_deref().unwrap_or_default());
+		for v in versions{
+			let date = DateTime::<Local>::from(UNIX_EPOCH + Duration::from_secs(v.timestamp));
+			let format_date = format!("{}", date.format("%v %X"));	
+			let changes = match &v.changes {
+				_ if v.index == 1 => String::from("first version"),
+				Some(changes) if changes.is_empty() => String::from("no visible change"),
+				Some(changes) => changes.join(", "),
+				None => String::from("unknown change")
+			};
+			let mut flags = String::new();
+			if v.exported{
+				flags.push_str(" (exported)");
+			}
+			if v.pinned{
+				flags.push_str(" (pinned)");
 			}
+			println!("[{}]: {} - {}{}", v.index, format_date, changes, flags);
 		}
 	}
 }
 
-async fn export_playlist(db: &database::Database, playlist_id: &String, index: u64, conf: &ArchifyConf){
-	let client = &spotify::get_spotify_client_from_user(conf).await;
+async fn export_playlist(archive: &mut Archive, playlist_id: &String, version: &database::Version){
+	let p_id = get_playlist_id(playlist_id);
 
-	let p_id ;
-	if !PlaylistId::id_is_valid(playlist_id.as_str()){
-		p_id = PlaylistId::from_id(parse_url(playlist_id).unwrap()).unwrap();
+	if archive.version(&p_id, version).await.is_none(){
+		error!("No playlist with this id & index are stored. Check --tracked.");
+		return;
+	}
+
+	// The CLI may prompt the user to authorize archify, the library never does
+	match spotify::get_spotify_client_from_user(archive.conf()).await {
+		Ok(client) => archive.set_user_client(client),
+		Err(e) => {
+			error!("Spotify user cannot be authorized: {}", e);
+			return;
+		}
+	}
+
+	if let Err(e) = archive.export(&p_id, version, CONF_DEFAULT_EXPORT_TEMPLATE).await {
+		error!("{}", e);
+	}
+
+}
+
+fn extract_cover(db: &dyn database::Storage, args: arguments::VersionFileArgs){
+	let p_id = get_playlist_id(&args.playlist_id);
+
+	match archify::get_version(db, &p_id, &args.version) {
+		Some((index, database::Playlist { cover: Some(cover), .. })) => {
+			File::create(&args.path)
+			.unwrap()
+			.write_all(&cover)
+			.unwrap();
+			info!("Cover of version {} written to {}.", index, args.path);
+		},
+		Some(_) => error!("No cover archived for this version."),
+		None => error!("No playlist with this id & index are stored. Check --tracked.")
+	}
+}
+
+fn print_item(item: &items::Item){
+	let detail = match item.kind {
+		items::ItemKind::Episode => item.release_date.clone().unwrap_or_default(),
+		_ => item.album.clone().unwrap_or_default()
+	};
+	println!(
+		"{: >4}. {}{} - {} ({}, {}) <{}>",
+		item.position + 1, item.marker(), item.name, item.creator(), detail, item.format_duration(), item.uri
+	);
+}
+
+fn show_version(db: &dyn database::Storage, args: arguments::ExportArgs){
+	let p_id = get_playlist_id(&args.playlist_id);
+
+	match archify::get_version(db, &p_id, &args.version) {
+		Some((index, database::Playlist { data: Some(data), timestamp, .. })) => {
+			let date = DateTime::<Local>::from(UNIX_EPOCH + Duration::from_secs(timestamp));
+			println!("[{}] - {} - version {} ({}):", p_id.id(), data.name, index, date.format("%v %X"));
+			for item in items::get_items(&data){
+				print_item(&item);
+			}
+		},
+		_ => error!("No playlist with this id & index are stored. Check --tracked.")
+	}
+}
+
+fn diff_versions(db: &dyn database::Storage, args: arguments::DiffArgs){
+	let p_id = get_playlist_id(&args.playlist_id);
+
+	let old = archify::get_version(db, &p_id, &args.from).and_then(|(i, p)| p.data.map(|d| (i, d)));
+	let new = archify::get_version(db, &p_id, &args.to).and_then(|(i, p)| p.data.map(|d| (i, d)));
+
+	match (old, new) {
+		(Some((from, old)), Some((to, new))) => {
+			let d = diff::diff(&items::get_items(&old), &items::get_items(&new));
+			println!("[{}] - {} - version {} → {}:", p_id.id(), new.name, from, to);
+			if d.is_empty(){
+				println!("No item added or removed.");
+			}
+			for item in &d.removed {
+				print!("-");
+				print_item(item);
+			}
+			for item in &d.added {
+				print!("+");
+				print_item(item);
+			}
+		},
+		_ => error!("No playlist with this id & indexes are stored. Check --tracked.")
+	}
+}
+
+fn export_file(db: &dyn database::Storage, args: arguments::VersionFileArgs){
+	let p_id = get_playlist_id(&args.playlist_id);
+
+	match archify::get_version(db, &p_id, &args.version).and_then(|(_, p)| p.data) {
+		Some(data) => {
+			let items = items::get_items(&data);
+			let mut file = File::create(&args.path).unwrap();
+			if args.path.ends_with(".json") {
+				serde_json::to_writer_pretty(&mut file, &items).unwrap();
+			}else{
+				items::write_csv(&items, &mut file).unwrap();
+			}
+			let local = items.iter().filter(|i| i.kind == items::ItemKind::Local).count();
+			info!("{} items written to {}, {} of them local.", items.len(), args.path, local);
+		},
+		None => error!("No playlist with this id & index are stored. Check --tracked.")
+	}
+}
+
+fn print_followers(db: &dyn database::Storage, args: arguments::FollowersArgs){
+	let observations = db.get_observations(&get_playlist_id(&args.playlist_id));
+
+	if observations.is_empty(){
+		error!("No follower count recorded for this playlist! Do an --update first.");
 	}else{
-		p_id = PlaylistId::from_id(playlist_id).unwrap();
+		stats::print_followers(&observations, args.format);
 	}
+}
 
-	let playlist = db.get_playlist_from_tracked_index(&p_id, index);
+fn print_playlist_stats(db: &dyn database::Storage, args: arguments::StatsArgs){
+	let versions = db.get_all_items(&get_playlist_id(&args.playlist_id));
 
-	match playlist {
-		Some(p) => spotify::export_playlist_to_user(client, &p).await,
-		None =>	error!("No playlist with this id & index are stored. Check --tracked.")
+	if versions.is_empty(){
+		error!("No version recorded for this playlist! Do an --update first.");
+	}else{
+		stats::print_playlist_stats(&stats::get_playlist_stats(&versions), args.format);
 	}
+}
+
+fn print_contributors(db: &dyn database::Storage, playlist_id: &String){
+	let versions = db.get_all_items(&get_playlist_id(playlist_id));
 
+	if versions.is_empty(){
+		error!("No version recorded for this playlist! Do an --update first.");
+	}else{
+		println!("Contributors across {} version(s):", versions.len());
+		stats::print_contributors(&versions);
+	}
+}
+
+fn get_track_uri(track: &str) -> Option<String>{
+	let id = match Url::parse(track) {
+		Ok(url) if url.scheme() != "spotify" => {
+			let segments = url.path_segments().map(|c| c.collect::<Vec<_>>()).unwrap_or_default();
+			match segments.as_slice() {
+				["track", id, ..] => String::from(*id),
+				_ => return None
+			}
+		},
+		_ => String::from(track)
+	};
+
+	TrackId::from_id_or_uri(&id).ok().map(|t| t.uri())
+}
+
+fn print_track_history(db: &dyn database::Storage, args: arguments::TrackHistoryArgs){
+	let uri = match get_track_uri(&args.track) {
+		Some(uri) => uri,
+		None => {
+			error!("{} is not a valid track URI, URL or ID.", args.track);
+			return;
+		}
+	};
+
+	let histories: Vec<history::PlaylistHistory> = db.get_playlists_containing(&uri)
+		.into_iter()
+		.map(|p_id| history::PlaylistHistory {
+			playlist_id: p_id.id().to_string(),
+			playlist_name: db.get_playlist_name(&p_id),
+			ranges: history::get_ranges(&db.get_all_items(&p_id), &uri)
+		})
+		.collect();
+
+	match args.format {
+		report::ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&histories).unwrap()),
+		report::ReportFormat::Text => {
+			if histories.is_empty(){
+				println!("{} was never archived.", uri);
+			}
+			for h in histories {
+				println!("[{}] - {}:", h.playlist_id, h.playlist_name.unwrap_or_default());
+				for r in h.ranges {
+					let first = DateTime::<Local>::from(UNIX_EPOCH + Duration::from_secs(r.first_timestamp));
+					let last = DateTime::<Local>::from(UNIX_EPOCH + Duration::from_secs(r.last_timestamp));
+					println!(
+						"  [{}] {} → [{}] {}, position(s) {}",
+						r.first_index, first.format("%v %X"), r.last_index, last.format("%v %X"),
+						r.positions.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ")
+					);
+				}
+			}
+		}
+	}
+}
+
+fn compare_playlists(db: &dyn database::Storage, args: arguments::CompareArgs){
+	let mut versions = Vec::new();
+
+	for (playlist_id, version) in &args.playlists {
+		let p_id = get_playlist_id(playlist_id);
+		match archify::get_version(db, &p_id, version) {
+			Some((index, database::Playlist { timestamp, .. })) => {
+				let items = db.get_version_items(&p_id, timestamp);
+				let v = compare::ComparedVersion::new(p_id.id().to_string(), db.get_playlist_name(&p_id), index, timestamp, &items);
+				versions.push((v, items));
+			},
+			None => {
+				error!("No version of {} matches. Check --tracked.", playlist_id);
+				return;
+			}
+		}
+	}
+
+	compare::print_comparison(&compare::compare(versions), args.format);
+}
+
+fn format_retention(policy: &retention::RetentionPolicy) -> String{
+	let rules: Vec<String> = [
+		("last", policy.keep_last),
+		("days", policy.keep_days),
+		("daily", policy.keep_daily),
+		("weekly", policy.keep_weekly),
+		("monthly", policy.keep_monthly)
+	].iter()
+		.filter_map(|(name, value)| value.map(|v| format!("keep-{} {}", name, v)))
+		.collect();
+
+	match rules.is_empty() {
+		true => String::from("keep everything"),
+		false => rules.join(", ")
+	}
+}
+
+fn set_retention(db: &dyn database::Storage, args: arguments::RetentionArgs, conf: &ArchifyConf){
+	let p_id = get_playlist_id(&args.playlist_id);
+
+	if args.clear {
+		db.set_retention(&p_id, None);
+	}else if let Some(policy) = &args.policy {
+		db.set_retention(&p_id, Some(policy));
+	}
+
+	match db.get_retention(&p_id) {
+		Some(policy) => println!("[{}]: {}", p_id.id(), format_retention(&policy)),
+		None => println!("[{}]: {} (global)", p_id.id(), format_retention(&conf.retention))
+	}
+}
+
+fn pin_version(db: &dyn database::Storage, args: arguments::PinArgs){
+	let p_id = get_playlist_id(&args.playlist_id);
+
+	match archify::get_version(db, &p_id, &args.version) {
+		Some((index, p)) => {
+			db.set_pinned(&p_id, p.timestamp, args.pinned);
+			info!("Version {} of {} {}.", index, p_id.id(), if args.pinned { "pinned" } else { "unpinned" });
+		},
+		None => error!("No playlist with this id & index are stored. Check --tracked.")
+	}
+}
+
+fn prune(db: &dyn database::Storage, args: arguments::PruneArgs, conf: &ArchifyConf){
+	let playlist_ids: Vec<PlaylistId<'static>> = match args.playlist_ids.is_empty() {
+		true => db.get_latest_unique_playlists().into_iter().map(|p| p.id).collect(),
+		false => args.playlist_ids.iter().map(get_playlist_id).collect()
+	};
+	let now = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.unwrap()
+		.as_secs();
+
+	for p_id in playlist_ids {
+		let policy = db.get_retention(&p_id).unwrap_or_else(|| conf.retention.clone());
+		let timestamps = db.get_version_timestamps(&p_id);
+		let pruned = retention::get_pruned_timestamps(&policy, &timestamps, &db.get_pinned_timestamps(&p_id), now);
+
+		println!("[{}]: {} - {} of {} version(s) to delete", p_id.id(), format_retention(&policy), pruned.len(), timestamps.len());
+		for timestamp in pruned {
+			let index = timestamps.iter().position(|t| *t == timestamp).unwrap() + 1;
+			let date = DateTime::<Local>::from(UNIX_EPOCH + Duration::from_secs(timestamp));
+			if args.dry_run {
+				println!("  would delete [{}]: {} (timestamp {})", index, date.format("%v %X"), timestamp);
+			}else{
+				db.delete_version(&p_id, timestamp);
+				println!("  deleted [{}]: {} (timestamp {})", index, date.format("%v %X"), timestamp);
+			}
+		}
+	}
+}
+
+fn search(db: &dyn database::Storage, args: arguments::SearchArgs){
+	if args.rebuild {
+		db.rebuild_search_index();
+	}
+
+	let query = match args.query {
+		Some(query) => query,
+		None => return
+	};
+
+	let hits = match db.search(&query) {
+		Ok(hits) => hits,
+		Err(e) => {
+			error!("Invalid search query {}: {}", query, e);
+			return;
+		}
+	};
+	let matches = search::group_hits(hits, |p_id| db.get_version_timestamps(p_id));
+
+	match args.format {
+		report::ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&matches).unwrap()),
+		report::ReportFormat::Text => {
+			if matches.is_empty(){
+				println!("Nothing matches {}.", query);
+			}
+			for m in matches {
+				let artists = match m.artists.is_empty() {
+					true => String::new(),
+					false => format!(" - {}", m.artists.join(", "))
+				};
+				println!("{}{} ({}) [{}]", m.name, artists, m.album.unwrap_or_default(), m.uri);
+				for p in m.playlists {
+					println!(
+						"  [{}] - {}: version(s) {}",
+						p.playlist_id, p.playlist_name.unwrap_or_default(),
+						p.versions.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ")
+					);
+				}
+			}
+		}
+	}
 }
 
 fn main() {
-	println!("Welcome to archify!");
+	eprintln!("Welcome to archify!");
 
 	#[cfg(debug_assertions)]
 	env::set_var("RUST_LOG", "debug");
@@ -196,21 +587,60 @@ fn main() {
 	}).init();
 
 
-	let _instance  = verify_single_instance();
-
 	let args = arguments::parse_args();
 	let conf = extract_configuration();
 
-	let db = database::Database::new();
+	let mut archive = match Archive::open(conf) {
+		Ok(archive) => archive,
+		Err(e) => {
+			error!("{}", e);
+			exit(-1);
+		}
+	};
+	let db = archive.db();
 
 
 	match args{
-		arguments::Args::NewPlaylist(playlists) => add_playlist(&db, playlists),
-		arguments::Args::Update => Runtime::new().unwrap().block_on(update_playlists(&db, &conf)),
-		arguments::Args::DeletePlaylist(playlists) => delete_playlist(&db, playlists),
-		arguments::Args::List => Runtime::new().unwrap().block_on(list_playlists(&db)),
-		arguments::Args::Tracked(playlist_id) => list_tracked_versions(&db, &playlist_id),
-		arguments::Args::Export(export) => Runtime::new().unwrap().block_on(export_playlist(&db, &export.playlist_id, export.index, &conf))
+		arguments::Args::NewPlaylist(playlists) => Runtime::new().unwrap().block_on(add_playlist(&archive, playlists)),
+		arguments::Args::Update(force) => {
+			let _instance = verify_single_instance();
+			if let Err(e) = Runtime::new().unwrap().block_on(archive.update(force)) {
+				error!("{}", e);
+			}
+		},
+		arguments::Args::DeletePlaylist(args) => Runtime::new().unwrap().block_on(delete_playlist(&archive, args)),
+		arguments::Args::List => Runtime::new().unwrap().block_on(list_playlists(&archive)),
+		arguments::Args::Tracked(playlist_id) => Runtime::new().unwrap().block_on(list_tracked_versions(&archive, &playlist_id)),
+		arguments::Args::Export(export) => Runtime::new().unwrap().block_on(export_playlist(&mut archive, &export.playlist_id, &export.version)),
+		arguments::Args::AutoExport(auto_export) => set_auto_export(db, auto_export),
+		arguments::Args::ExtractCover(args) => extract_cover(db, args),
+		arguments::Args::Followers(args) => print_followers(db, args),
+		arguments::Args::Stats(args) => print_playlist_stats(db, args),
+		arguments::Args::Show(args) => show_version(db, args),
+		arguments::Args::Diff(args) => diff_versions(db, args),
+		arguments::Args::ExportFile(args) => export_file(db, args),
+		arguments::Args::Contributors(playlist_id) => print_contributors(db, &playlist_id),
+		arguments::Args::TrackHistory(args) => print_track_history(db, args),
+		arguments::Args::Compare(args) => compare_playlists(db, args),
+		arguments::Args::Retention(args) => set_retention(db, args, archive.conf()),
+		arguments::Args::Pin(args) => pin_version(db, args),
+		arguments::Args::Prune(args) => prune(db, args, archive.conf()),
+		arguments::Args::Schedule(args) => set_schedule(&archive, args),
+		arguments::Args::Daemon => {
+			let _instance = verify_single_instance();
+			Runtime::new().unwrap().block_on(daemon::run(&mut archive));
+		},
+		arguments::Args::Serve(args) => {
+			let token = env::var(CONF_SERVE_TOKEN_ENV).ok().or_else(|| archive.conf().serve_token.clone());
+			server::run(&mut archive, &args.address, token)
+		},
+		arguments::Args::TestNotifications => Runtime::new().unwrap().block_on(test_notifications(archive.conf())),
+		arguments::Args::Pause(playlists, paused) => pause_playlists(db, playlists, paused),
+		arguments::Args::TrashList => list_trash(db),
+		arguments::Args::TrashRestore(playlists) => restore_trash(db, playlists),
+		arguments::Args::TrashPurge(older_than) => purge_trash(db, older_than),
+		arguments::Args::SiteBuild(path) => Runtime::new().unwrap().block_on(site::build(&archive, &path)),
+		arguments::Args::Search(args) => search(db, args)
 	}
 
 }
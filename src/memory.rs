@@ -0,0 +1,431 @@
+use crate::conf::*;
+use crate::database::{ExportResult, Observation, Playlist, Playlists, SearchHit, Storage, TrashEntry};
+use crate::items::{self, Item};
+use crate::retention::RetentionPolicy;
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+use log::info;
+use rspotify::model::PlaylistId;
+use rspotify::prelude::Id;
+use sha2::{Digest, Sha256};
+
+struct StoredVersion {
+	/// Stored without its cover bytes, kept once in `covers`.
+	playlist: Playlist,
+	items: Vec<Item>,
+	pinned: bool
+}
+
+#[derive(Default)]
+struct Settings {
+	auto_export: bool,
+	export_template: Option<String>,
+	retention: RetentionPolicy,
+	schedule: Option<String>,
+	paused: bool
+}
+
+#[derive(Default)]
+struct State {
+	/// Versions of each playlist in ascending order, placeholder first.
+	playlists: BTreeMap<String, Vec<StoredVersion>>,
+	settings: HashMap<String, Settings>,
+	covers: HashMap<[u8; 32], Vec<u8>>,
+	/// Playlist, timestamp of the version and whether the export succeeded.
+	exports: Vec<(String, u64, bool)>,
+	observations: HashMap<String, BTreeMap<u64, Observation>>,
+	trash: HashMap<String, u64>
+}
+
+impl State {
+	fn versions(&self, playlist_id: &PlaylistId) -> &[StoredVersion] {
+		self.playlists.get(playlist_id.id()).map(Vec::as_slice).unwrap_or_default()
+	}
+
+	/// Archived versions of a playlist, without the placeholder.
+	fn archived(&self, playlist_id: &PlaylistId) -> impl Iterator<Item = &StoredVersion> {
+		self.versions(playlist_id).iter().filter(|v| v.playlist.timestamp != CONF_TIME_BIG_BANG as u64)
+	}
+
+	/// Deletes the covers left without any version.
+	fn delete_unused_covers(&mut self){
+		let State { playlists, covers, .. } = self;
+		covers.retain(|sha256, _| {
+			playlists.values().flat_map(|versions| versions.iter()).any(|v| v.playlist.cover_sha256 == Some(*sha256))
+		});
+	}
+
+	fn name(&self, playlist_id: &PlaylistId) -> Option<String> {
+		self.versions(playlist_id).iter().rev().find_map(|v| v.playlist.metadata.as_ref().map(|m| m.name.clone()))
+	}
+}
+
+fn copy_playlist(playlist: &Playlist, count: u64) -> Playlist {
+	Playlist { count, cover: None, ..playlist.clone() }
+}
+
+fn copy_observation(observation: &Observation) -> Observation {
+	Observation {
+		id: observation.id.clone_static(),
+		timestamp: observation.timestamp,
+		followers: observation.followers,
+		snapshot_id: observation.snapshot_id.clone(),
+		item_count: observation.item_count
+	}
+}
+
+/// Storage kept in memory and lost when dropped, for tests and tools that must not touch `data/db.sqlite`.
+///
+/// `search` does not understand the FTS5 syntax of `SqliteStorage`: it matches the items whose title, artists, album
+/// or playlist name contain every word of the query, ignoring case.
+///
+/// ```
+/// use archify::{Archive, ArchifyConf, MemoryStorage, Storage, Version};
+///
+/// let archive = Archive::new(Box::new(MemoryStorage::new()), ArchifyConf::new("client id", "client secret"));
+/// let id = archify::parse_playlist_id("37i9dQZEVXcQ9COmYvdajy").unwrap();
+/// archive.db().set_unique_empty_playlist(&id);
+///
+/// assert_eq!(archive.db().get_latest_unique_playlists().len(), 1);
+/// assert_eq!(archive.db().get_version_index(&id, &Version::Latest), None);
+/// ```
+#[derive(Default)]
+pub struct MemoryStorage {
+	state: RefCell<State>
+}
+
+impl MemoryStorage {
+	pub fn new() -> Self {
+		MemoryStorage::default()
+	}
+}
+
+impl Storage for MemoryStorage {
+	fn rebuild_search_index(&self){
+		info!("Search index rebuilt.");
+	}
+
+	fn search(&self, query: &str) -> Result<Vec<SearchHit>, String>{
+		let words: Vec<String> = query.split_whitespace()
+			.map(|w| w.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase())
+			.filter(|w| !w.is_empty())
+			.collect();
+		if words.is_empty() {
+			return Err(format!("no word to search in {}", query));
+		}
+
+		let state = self.state.borrow();
+		let mut hits = Vec::new();
+		for v in state.playlists.values().flat_map(|versions| versions.iter()) {
+			let playlist_name = v.playlist.metadata.as_ref().map(|m| m.name.clone());
+			for item in &v.items {
+				let album = item.album.clone().or_else(|| item.show.clone());
+				let text = format!(
+					"{} {} {} {}",
+					item.name, item.artists.join(" "), album.as_deref().unwrap_or_default(), playlist_name.as_deref().unwrap_or_default()
+				).to_lowercase();
+				if words.iter().all(|w| text.contains(w.as_str())) {
+					hits.push(SearchHit {
+						playlist_id: v.playlist.id.clone_static(),
+						playlist_name: playlist_name.clone(),
+						timestamp: v.playlist.timestamp,
+						uri: item.uri.clone(),
+						name: item.name.clone(),
+						artists: item.artists.clone(),
+						album
+					});
+				}
+			}
+		}
+		Ok(hits)
+	}
+
+	/// Approximate size of the stored versions and covers.
+	fn get_size(&self) -> u64{
+		let state = self.state.borrow();
+		let versions: usize = state.playlists.values()
+			.flat_map(|versions| versions.iter())
+			.map(|v| serde_json::to_string(&v.playlist.data).unwrap().len())
+			.sum();
+		let covers: usize = state.covers.values().map(Vec::len).sum();
+		(versions + covers) as u64
+	}
+
+	fn get_version_timestamps(&self, playlist_id: &PlaylistId) -> Vec<u64>{
+		self.state.borrow().archived(playlist_id).map(|v| v.playlist.timestamp).collect()
+	}
+
+	fn set_unique_empty_playlist(&self, playlist_id: &PlaylistId){
+		if self.state.borrow().playlists.contains_key(playlist_id.id()) {
+			if !self.restore_playlist(playlist_id) {
+				info!("Playlist {} is already present.", playlist_id);
+			}
+			return;
+		}
+
+		self.state.borrow_mut().playlists.insert(playlist_id.id().to_string(), vec![StoredVersion {
+			playlist: Playlist {
+				id: playlist_id.clone_static(),
+				sha256: CONF_SHA256_NULL,
+				tracks_sha256: None,
+				cover_sha256: None,
+				timestamp: CONF_TIME_BIG_BANG as u64,
+				count: 0,
+				metadata: None,
+				changes: None,
+				data: CONF_NULL_PLAYLIST_DATA,
+				cover: None
+			},
+			items: Vec::new(),
+			pinned: false
+		}]);
+		info!("Empty playlist {} inserted.", playlist_id);
+	}
+
+	fn get_all_items(&self, playlist_id: &PlaylistId) -> Vec<(u64, Vec<Item>)>{
+		self.state.borrow().archived(playlist_id).map(|v| (v.playlist.timestamp, v.items.clone())).collect()
+	}
+
+	fn get_version_items(&self, playlist_id: &PlaylistId, timestamp: u64) -> Vec<Item>{
+		self.state.borrow().versions(playlist_id)
+			.iter()
+			.find(|v| v.playlist.timestamp == timestamp)
+			.map(|v| v.items.clone())
+			.unwrap_or_default()
+	}
+
+	fn get_playlists_containing(&self, uri: &str) -> Vec<PlaylistId<'static>>{
+		self.state.borrow().playlists.values()
+			.filter(|versions| versions.iter().any(|v| v.items.iter().any(|i| i.uri == uri)))
+			.map(|versions| versions[0].playlist.id.clone_static())
+			.collect()
+	}
+
+	fn get_playlist_name(&self, playlist_id: &PlaylistId) -> Option<String>{
+		self.state.borrow().name(playlist_id)
+	}
+
+	fn get_cover(&self, sha256: &[u8; 32]) -> Option<Vec<u8>>{
+		self.state.borrow().covers.get(sha256).cloned()
+	}
+
+	fn set_playlist(&self, playlist: &Playlist){
+		let mut state = self.state.borrow_mut();
+		if let Some(cover) = &playlist.cover {
+			let sha256: [u8; 32] = Sha256::digest(cover).into();
+			state.covers.entry(sha256).or_insert_with(|| cover.clone());
+		}
+
+		let versions = state.playlists.entry(playlist.id.id().to_string()).or_default();
+		let position = versions.partition_point(|v| v.playlist.timestamp < playlist.timestamp);
+		versions.insert(position, StoredVersion {
+			playlist: copy_playlist(playlist, 0),
+			items: playlist.data.as_ref().map(items::get_items).unwrap_or_default(),
+			pinned: false
+		});
+		info!("Playlist {} inserted.", playlist.id.id());
+	}
+
+	fn set_observation(&self, observation: &Observation){
+		self.state.borrow_mut().observations
+			.entry(observation.id.id().to_string())
+			.or_default()
+			.insert(observation.timestamp, copy_observation(observation));
+	}
+
+	fn get_last_check(&self, playlist_id: &PlaylistId) -> u64{
+		let state = self.state.borrow();
+		let observed = state.observations.get(playlist_id.id()).and_then(|o| o.keys().next_back().copied());
+		let archived = state.versions(playlist_id).last().map(|v| v.playlist.timestamp);
+		observed.max(archived).unwrap_or(0)
+	}
+
+	fn get_observations(&self, playlist_id: &PlaylistId) -> Vec<Observation>{
+		self.state.borrow().observations
+			.get(playlist_id.id())
+			.map(|o| o.values().map(copy_observation).collect())
+			.unwrap_or_default()
+	}
+
+	fn delete_playlist(&self, playlist_id: &PlaylistId){
+		let mut state = self.state.borrow_mut();
+		state.playlists.remove(playlist_id.id());
+		state.settings.remove(playlist_id.id());
+		state.observations.remove(playlist_id.id());
+		state.trash.remove(playlist_id.id());
+		state.exports.retain(|(id, _, _)| id != playlist_id.id());
+		state.delete_unused_covers();
+		info!("Playlist(s) {} deleted.", playlist_id);
+	}
+
+	fn trash_playlist(&self, playlist_id: &PlaylistId, deleted_at: u64){
+		let mut state = self.state.borrow_mut();
+		if state.playlists.contains_key(playlist_id.id()) && !state.trash.contains_key(playlist_id.id()) {
+			state.trash.insert(playlist_id.id().to_string(), deleted_at);
+			info!("Playlist {} moved to trash.", playlist_id);
+		}else{
+			info!("Playlist {} is not tracked.", playlist_id);
+		}
+	}
+
+	fn restore_playlist(&self, playlist_id: &PlaylistId) -> bool{
+		let restored = self.state.borrow_mut().trash.remove(playlist_id.id()).is_some();
+		if restored {
+			info!("Playlist {} restored from trash.", playlist_id);
+		}
+		restored
+	}
+
+	fn get_trashed_playlists(&self) -> Vec<TrashEntry>{
+		let state = self.state.borrow();
+		let mut entries: Vec<TrashEntry> = state.trash.iter()
+			.filter_map(|(id, deleted_at)| {
+				let playlist_id = state.playlists.get(id)?.first()?.playlist.id.clone_static();
+				Some(TrashEntry {
+					deleted_at: *deleted_at,
+					name: state.name(&playlist_id),
+					versions: state.archived(&playlist_id).count() as u64,
+					id: playlist_id
+				})
+			})
+			.collect();
+		entries.sort_by_key(|t| t.deleted_at);
+		entries
+	}
+
+	fn set_auto_export(&self, playlist_id: &PlaylistId, enabled: bool, template: Option<&str>){
+		let mut state = self.state.borrow_mut();
+		let settings = state.settings.entry(playlist_id.id().to_string()).or_default();
+		settings.auto_export = enabled;
+		if let Some(template) = template {
+			settings.export_template = Some(String::from(template));
+		}
+		info!("Auto-export of playlist {} set to {}.", playlist_id.id(), enabled);
+	}
+
+	fn get_auto_export(&self, playlist_id: &PlaylistId) -> Option<String>{
+		self.state.borrow().settings
+			.get(playlist_id.id())
+			.filter(|s| s.auto_export)
+			.map(|s| s.export_template.clone().unwrap_or_else(|| String::from(CONF_DEFAULT_EXPORT_TEMPLATE)))
+	}
+
+	fn set_retention(&self, playlist_id: &PlaylistId, policy: Option<&RetentionPolicy>){
+		self.state.borrow_mut().settings.entry(playlist_id.id().to_string()).or_default().retention = policy.cloned().unwrap_or_default();
+		info!("Retention policy of playlist {} set.", playlist_id.id());
+	}
+
+	fn get_retention(&self, playlist_id: &PlaylistId) -> Option<RetentionPolicy>{
+		self.state.borrow().settings
+			.get(playlist_id.id())
+			.map(|s| s.retention.clone())
+			.filter(|p| !p.is_empty())
+	}
+
+	fn set_schedule(&self, playlist_id: &PlaylistId, schedule: Option<&str>){
+		self.state.borrow_mut().settings.entry(playlist_id.id().to_string()).or_default().schedule = schedule.map(String::from);
+		info!("Schedule of playlist {} set to {}.", playlist_id.id(), schedule.unwrap_or("global"));
+	}
+
+	fn get_schedule(&self, playlist_id: &PlaylistId) -> Option<String>{
+		self.state.borrow().settings.get(playlist_id.id()).and_then(|s| s.schedule.clone())
+	}
+
+	fn set_paused(&self, playlist_id: &PlaylistId, paused: bool){
+		self.state.borrow_mut().settings.entry(playlist_id.id().to_string()).or_default().paused = paused;
+		info!("Playlist {} {}.", playlist_id.id(), if paused { "paused" } else { "resumed" });
+	}
+
+	fn is_paused(&self, playlist_id: &PlaylistId) -> bool{
+		self.state.borrow().settings.get(playlist_id.id()).is_some_and(|s| s.paused)
+	}
+
+	fn set_pinned(&self, playlist_id: &PlaylistId, timestamp: u64, pinned: bool){
+		let mut state = self.state.borrow_mut();
+		let version = state.playlists.get_mut(playlist_id.id())
+			.and_then(|versions| versions.iter_mut().find(|v| v.playlist.timestamp == timestamp));
+		if let Some(version) = version {
+			version.pinned = pinned;
+		}
+	}
+
+	fn get_pinned_timestamps(&self, playlist_id: &PlaylistId) -> Vec<u64>{
+		self.state.borrow().versions(playlist_id)
+			.iter()
+			.filter(|v| v.pinned)
+			.map(|v| v.playlist.timestamp)
+			.collect()
+	}
+
+	fn delete_version(&self, playlist_id: &PlaylistId, timestamp: u64){
+		let mut state = self.state.borrow_mut();
+		if let Some(versions) = state.playlists.get_mut(playlist_id.id()) {
+			if let Some(i) = versions.iter().position(|v| v.playlist.timestamp == timestamp) {
+				versions.remove(i);
+				// The following version now comes after the one preceding the deleted version
+				if i > 0 && i < versions.len() {
+					let changes = versions[i].playlist.changes_since(&versions[i - 1].playlist);
+					versions[i].playlist.changes = Some(changes);
+				}
+			}
+		}
+		state.exports.retain(|(id, t, _)| id != playlist_id.id() || *t != timestamp);
+		state.delete_unused_covers();
+		info!("Version {} of playlist {} deleted.", timestamp, playlist_id.id());
+	}
+
+	fn set_export_result(&self, export: &ExportResult){
+		self.state.borrow_mut().exports.push((export.id.id().to_string(), export.timestamp, export.error.is_none()));
+		info!("Export result of playlist {} recorded.", export.id.id());
+	}
+
+	fn get_exported_timestamps(&self, playlist_id: &PlaylistId) -> Vec<u64>{
+		let mut timestamps: Vec<u64> = self.state.borrow().exports
+			.iter()
+			.filter(|(id, _, success)| id == playlist_id.id() && *success)
+			.map(|(_, timestamp, _)| *timestamp)
+			.collect();
+		timestamps.sort();
+		timestamps.dedup();
+		timestamps
+	}
+
+	fn get_latest_unique_playlists(&self) -> Playlists {
+		let state = self.state.borrow();
+		let playlists: Playlists = state.playlists.iter()
+			.filter(|(id, _)| !state.trash.contains_key(*id))
+			.filter_map(|(_, versions)| versions.last().map(|v| copy_playlist(&v.playlist, versions.len() as u64)))
+			.collect();
+		info!("{} latest unique playlists retreived.", playlists.len());
+		playlists
+	}
+
+	fn get_all_tracked_versions(&self, playlist_id: &PlaylistId) -> Playlists{
+		self.state.borrow().versions(playlist_id)
+			.iter()
+			.enumerate()
+			.map(|(i, v)| copy_playlist(&v.playlist, i as u64))
+			.collect()
+	}
+
+	fn get_latest_tracked_versions(&self, playlist_id: &PlaylistId, limit: u64) -> Playlists{
+		let state = self.state.borrow();
+		let versions = state.versions(playlist_id);
+		let first = versions.len().saturating_sub(limit as usize);
+		versions[first..]
+			.iter()
+			.enumerate()
+			.map(|(i, v)| copy_playlist(&v.playlist, (first + i) as u64))
+			.collect()
+	}
+
+	fn get_playlist_from_tracked_index(&self, playlist_id: &PlaylistId, index: u64) -> Option<Playlist>{
+		let state = self.state.borrow();
+		let version = state.versions(playlist_id).get(index as usize)?;
+		let mut playlist = copy_playlist(&version.playlist, index);
+		playlist.cover = version.playlist.cover_sha256.and_then(|sha256| state.covers.get(&sha256).cloned());
+		Some(playlist)
+	}
+}
@@ -0,0 +1,193 @@
+use crate::database;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use log::{error, info};
+use rspotify::prelude::Id;
+
+// Counters of this process, reset when it restarts
+static API_CALLS: AtomicU64 = AtomicU64::new(0);
+static RETRIES: AtomicU64 = AtomicU64::new(0);
+static RATE_LIMITED: AtomicU64 = AtomicU64::new(0);
+static VERSIONS_INSERTED: AtomicU64 = AtomicU64::new(0);
+static UPDATE_RUNS: AtomicU64 = AtomicU64::new(0);
+static FAILURES: Mutex<BTreeMap<String, u64>> = Mutex::new(BTreeMap::new());
+// Whether this process makes several runs of updates, e.g. as a daemon, so that its counters are worth exporting
+static LONG_RUNNING: AtomicBool = AtomicBool::new(false);
+
+// Last run of updates, the only metrics meaningful when every run is its own process, e.g. with cron
+static LAST_RUN: Mutex<Option<LastRun>> = Mutex::new(None);
+
+#[derive(Clone, Copy)]
+struct Counters {
+	api_calls: u64,
+	retries: u64,
+	rate_limited: u64,
+	versions_inserted: u64,
+	failures: u64
+}
+
+impl Counters {
+	fn now() -> Counters {
+		Counters {
+			api_calls: API_CALLS.load(Ordering::Relaxed),
+			retries: RETRIES.load(Ordering::Relaxed),
+			rate_limited: RATE_LIMITED.load(Ordering::Relaxed),
+			versions_inserted: VERSIONS_INSERTED.load(Ordering::Relaxed),
+			failures: FAILURES.lock().unwrap().values().sum()
+		}
+	}
+
+	fn since(&self, start: &Counters) -> Counters {
+		Counters {
+			api_calls: self.api_calls - start.api_calls,
+			retries: self.retries - start.retries,
+			rate_limited: self.rate_limited - start.rate_limited,
+			versions_inserted: self.versions_inserted - start.versions_inserted,
+			failures: self.failures - start.failures
+		}
+	}
+}
+
+struct LastRun {
+	timestamp: u64,
+	duration_ms: u64,
+	counters: Counters
+}
+
+/// Run of updates over the due playlists, from `start_run` until it is recorded by `record_update`.
+pub struct Run {
+	started: Instant,
+	counters: Counters
+}
+
+pub fn start_run() -> Run {
+	Run { started: Instant::now(), counters: Counters::now() }
+}
+
+/// Includes the counters of this process in the textfile, for a process making several runs of updates.
+pub fn set_long_running(){
+	LONG_RUNNING.store(true, Ordering::Relaxed);
+}
+
+pub fn inc_api_calls(){
+	API_CALLS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn inc_retries(){
+	RETRIES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn inc_rate_limited(){
+	RATE_LIMITED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn inc_versions_inserted(){
+	VERSIONS_INSERTED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn inc_failures(playlist_id: &str){
+	*FAILURES.lock().unwrap().entry(String::from(playlist_id)).or_default() += 1;
+}
+
+/// Records a run of updates over the due playlists.
+pub fn record_update(run: Run){
+	UPDATE_RUNS.fetch_add(1, Ordering::Relaxed);
+	let timestamp = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.unwrap()
+		.as_secs();
+	*LAST_RUN.lock().unwrap() = Some(LastRun {
+		timestamp,
+		duration_ms: run.started.elapsed().as_millis() as u64,
+		counters: Counters::now().since(&run.counters)
+	});
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(String, String)]){
+	writeln!(out, "# HELP {} {}", name, help).unwrap();
+	writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+	for (labels, value) in samples {
+		writeln!(out, "{}{} {}", name, labels, value).unwrap();
+	}
+}
+
+fn label(playlist_id: &str) -> String {
+	format!("{{playlist=\"{}\"}}", playlist_id.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Gauges of the database, then of the last run of updates in this process.
+fn render_state(db: &dyn database::Storage) -> String {
+	let mut out = String::new();
+	let playlists = db.get_latest_unique_playlists();
+
+	metric(&mut out, "archify_playlists_tracked", "gauge", "Number of tracked playlists.",
+		&[(String::new(), playlists.len().to_string())]);
+	metric(&mut out, "archify_database_size_bytes", "gauge", "Size of the SQLite database.",
+		&[(String::new(), db.get_size().to_string())]);
+
+	let last_checks: Vec<(String, u64)> = playlists.iter()
+		.map(|p| (p.id.id().to_string(), db.get_last_check(&p.id)))
+		.collect();
+	let last_success = last_checks.iter().map(|(_, t)| *t).max().unwrap_or_default();
+	metric(&mut out, "archify_last_success_timestamp_seconds", "gauge", "Time of the last successful update of any playlist.",
+		&[(String::new(), last_success.to_string())]);
+	metric(&mut out, "archify_playlist_last_success_timestamp_seconds", "gauge", "Time of the last successful update of a playlist.",
+		&last_checks.iter().map(|(id, t)| (label(id), t.to_string())).collect::<Vec<_>>());
+
+	if let Some(run) = LAST_RUN.lock().unwrap().as_ref() {
+		let gauge = |value: u64| [(String::new(), value.to_string())];
+		metric(&mut out, "archify_last_run_timestamp_seconds", "gauge", "End of the last run of updates.", &gauge(run.timestamp));
+		metric(&mut out, "archify_last_run_duration_seconds", "gauge", "Duration of the last run of updates.",
+			&[(String::new(), format!("{:.3}", run.duration_ms as f64 / 1000.0))]);
+		metric(&mut out, "archify_last_run_api_calls", "gauge", "Requests sent to the Spotify API during the last run, retries included.",
+			&gauge(run.counters.api_calls));
+		metric(&mut out, "archify_last_run_api_retries", "gauge", "Requests to the Spotify API retried during the last run.",
+			&gauge(run.counters.retries));
+		metric(&mut out, "archify_last_run_api_rate_limited", "gauge", "429 Too Many Requests answers of the Spotify API during the last run.",
+			&gauge(run.counters.rate_limited));
+		metric(&mut out, "archify_last_run_versions_inserted", "gauge", "Playlist versions archived during the last run.",
+			&gauge(run.counters.versions_inserted));
+		metric(&mut out, "archify_last_run_update_failures", "gauge", "Playlists that failed to update during the last run.",
+			&gauge(run.counters.failures));
+	}
+
+	out
+}
+
+/// Metrics in the Prometheus text format, from the state of the database, the last run of updates and the counters
+/// of this process.
+pub fn render(db: &dyn database::Storage) -> String {
+	let mut out = render_state(db);
+	let counter = |c: &AtomicU64| vec![(String::new(), c.load(Ordering::Relaxed).to_string())];
+
+	metric(&mut out, "archify_update_runs_total", "counter", "Runs of updates over the due playlists.", &counter(&UPDATE_RUNS));
+	metric(&mut out, "archify_api_calls_total", "counter", "Requests sent to the Spotify API, retries included.", &counter(&API_CALLS));
+	metric(&mut out, "archify_api_retries_total", "counter", "Requests to the Spotify API retried after a 429 or 5xx answer.", &counter(&RETRIES));
+	metric(&mut out, "archify_api_rate_limited_total", "counter", "429 Too Many Requests answers of the Spotify API.", &counter(&RATE_LIMITED));
+	metric(&mut out, "archify_versions_inserted_total", "counter", "Playlist versions archived.", &counter(&VERSIONS_INSERTED));
+	metric(&mut out, "archify_update_failures_total", "counter", "Failed updates per playlist.",
+		&FAILURES.lock().unwrap().iter().map(|(id, n)| (label(id), n.to_string())).collect::<Vec<_>>());
+
+	out
+}
+
+/// Writes the metrics for the textfile collector of the node exporter, through a rename so that it never reads a partial file.
+/// The counters of the process are left out unless it is long running (see `set_long_running`): run by cron, each
+/// process makes a single run.
+pub fn write_textfile(db: &dyn database::Storage, path: &str){
+	let tmp = format!("{}.tmp", path);
+	let metrics = match LONG_RUNNING.load(Ordering::Relaxed) {
+		true => render(db),
+		false => render_state(db)
+	};
+	match fs::write(&tmp, metrics).and_then(|_| fs::rename(&tmp, path)) {
+		Ok(_) => info!("Metrics written to {}.", path),
+		Err(e) => error!("Metrics cannot be written to {}: {}", path, e)
+	}
+}
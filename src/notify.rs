@@ -0,0 +1,358 @@
+use std::process::Stdio;
+use std::time::{Duration, UNIX_EPOCH};
+
+use chrono::{DateTime, Local};
+use lettre::message::{Mailbox, Message};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::time::timeout;
+
+use crate::conf::*;
+
+/// Something that happened to a tracked playlist during an update.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+	NewVersion {
+		playlist_id: String,
+		playlist_name: String,
+		timestamp: u64,
+		/// Index as given by --tracked.
+		index: u64,
+		changes: Vec<String>,
+		added: usize,
+		removed: usize
+	},
+	/// The playlist cannot be found on Spotify anymore. It is paused, so that this is notified once.
+	DeletedUpstream {
+		playlist_id: String,
+		playlist_name: Option<String>,
+		timestamp: u64
+	},
+	UpdateFailed {
+		playlist_id: String,
+		playlist_name: Option<String>,
+		timestamp: u64,
+		error: String
+	}
+}
+
+impl Event {
+	pub fn summary(&self) -> String {
+		match self {
+			Event::NewVersion { playlist_id, playlist_name, timestamp, index, changes, added, removed } => format!(
+				"[{}] {}: version {} archived {} ({}; {} added, {} removed)",
+				playlist_id, playlist_name, index, format_timestamp(*timestamp), changes.join(", "), added, removed
+			),
+			Event::DeletedUpstream { playlist_id, playlist_name, timestamp } => format!(
+				"[{}] {}: not found on Spotify anymore {}",
+				playlist_id, playlist_name.as_deref().unwrap_or_default(), format_timestamp(*timestamp)
+			),
+			Event::UpdateFailed { playlist_id, playlist_name, timestamp, error } => format!(
+				"[{}] {}: update failed {}: {}",
+				playlist_id, playlist_name.as_deref().unwrap_or_default(), format_timestamp(*timestamp), error
+			)
+		}
+	}
+}
+
+fn format_timestamp(timestamp: u64) -> String {
+	let date = DateTime::<Local>::from(UNIX_EPOCH + Duration::from_secs(timestamp));
+	format!("{}", date.format("%v %X"))
+}
+
+/// Where events are sent, as configured in the `notifications` array of config.json.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Sink {
+	/// Runs a command for each event, with the event as JSON on stdin.
+	Command {
+		command: String,
+		#[serde(default)]
+		args: Vec<String>
+	},
+	/// POSTs each event as JSON.
+	Webhook {
+		url: String
+	},
+	/// Emails a digest of the events of an update.
+	Smtp(SmtpSink)
+}
+
+#[derive(Deserialize)]
+pub struct SmtpSink {
+	host: String,
+	port: Option<u16>,
+	/// Use TLS, otherwise the connection is in clear, e.g. for a local relay.
+	#[serde(default)]
+	tls: bool,
+	username: Option<String>,
+	password: Option<String>,
+	from: String,
+	to: Vec<String>
+}
+
+async fn run_command(command: &str, args: &[String], event: &Event) -> Result<(), String> {
+	let mut child = Command::new(command)
+		.args(args)
+		.stdin(Stdio::piped())
+		.kill_on_drop(true)
+		.spawn()
+		.map_err(|e| e.to_string())?;
+
+	// The child is waited for even if it does not read its stdin, so that it is not left as a zombie
+	let mut stdin = child.stdin.take().unwrap();
+	let run = timeout(Duration::from_secs(CONF_NOTIFY_TIMEOUT), async {
+		let written = stdin.write_all(&serde_json::to_vec(event).unwrap()).await;
+		drop(stdin);
+		(written, child.wait().await)
+	}).await;
+
+	let (written, status) = match run {
+		Ok(run) => run,
+		Err(_) => {
+			child.kill().await.map_err(|e| e.to_string())?;
+			return Err(format!("timed out after {}s", CONF_NOTIFY_TIMEOUT));
+		}
+	};
+	let status = status.map_err(|e| e.to_string())?;
+	written.map_err(|e| e.to_string())?;
+
+	match status.success() {
+		true => Ok(()),
+		false => Err(format!("exited with {}", status))
+	}
+}
+
+async fn post_webhook(url: &str, event: &Event) -> Result<(), String> {
+	reqwest::Client::builder()
+		.timeout(Duration::from_secs(CONF_NOTIFY_TIMEOUT))
+		.build()
+		.map_err(|e| e.to_string())?
+		.post(url)
+		.json(event)
+		.send()
+		.await
+		.and_then(|r| r.error_for_status())
+		.map(|_| ())
+		.map_err(|e| e.to_string())
+}
+
+async fn send_digest(smtp: &SmtpSink, events: &[Event]) -> Result<(), String> {
+	let mut message = Message::builder()
+		.from(smtp.from.parse::<Mailbox>().map_err(|e| e.to_string())?)
+		.subject(format!("Archify: {} event(s)", events.len()));
+	for recipient in &smtp.to {
+		message = message.to(recipient.parse::<Mailbox>().map_err(|e| e.to_string())?);
+	}
+	let body: Vec<String> = events.iter().map(|e| e.summary()).collect();
+	let message = message.body(body.join("\n")).map_err(|e| e.to_string())?;
+
+	let mut transport = match smtp.tls {
+		true => AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp.host).map_err(|e| e.to_string())?,
+		false => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&smtp.host)
+	};
+	transport = transport.timeout(Some(Duration::from_secs(CONF_NOTIFY_TIMEOUT)));
+	if let Some(port) = smtp.port {
+		transport = transport.port(port);
+	}
+	if let (Some(username), Some(password)) = (&smtp.username, &smtp.password) {
+		transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+	}
+
+	transport.build().send(message).await.map(|_| ()).map_err(|e| e.to_string())
+}
+
+/// Sends the events of an update to every sink. Failures are logged, never propagated:
+/// an event that cannot be sent does not keep the others from being sent.
+pub async fn send(sinks: &[Sink], events: &[Event]){
+	if events.is_empty() {
+		return;
+	}
+
+	for sink in sinks {
+		let mut res = Vec::new();
+		match sink {
+			Sink::Command { command, args } => {
+				for e in events {
+					res.push(run_command(command, args, e).await);
+				}
+			},
+			Sink::Webhook { url } => {
+				for e in events {
+					res.push(post_webhook(url, e).await);
+				}
+			},
+			Sink::Smtp(smtp) => res.push(send_digest(smtp, events).await)
+		};
+
+		let errors: Vec<String> = res.into_iter().filter_map(Result::err).collect();
+		if errors.is_empty() {
+			info!("{} event(s) notified.", events.len());
+		}
+		for e in errors {
+			error!("Notification cannot be sent: {}", e);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::io::{BufRead, BufReader, Read, Write};
+	use std::net::TcpListener;
+	use std::path::{Path, PathBuf};
+	use std::thread;
+
+	fn event(timestamp: u64) -> Event {
+		Event::DeletedUpstream {
+			playlist_id: String::from("37i9dQZEVXcQ9COmYvdajy"),
+			playlist_name: Some(String::from("Weekly")),
+			timestamp
+		}
+	}
+
+	fn temp_file(name: &str) -> PathBuf {
+		let path = std::env::temp_dir().join(format!("archify-notify-{}-{}", std::process::id(), name));
+		let _ = std::fs::remove_file(&path);
+		path
+	}
+
+	/// Sink appending each event to `path`, one per line, and exiting with `code`.
+	fn command_sink(path: &Path, code: u8) -> Sink {
+		Sink::Command {
+			command: String::from("sh"),
+			args: vec![String::from("-c"), format!("cat >> \"$0\"; echo >> \"$0\"; exit {}", code), path.display().to_string()]
+		}
+	}
+
+	fn received_timestamps(path: &Path) -> Vec<u64> {
+		std::fs::read_to_string(path)
+			.unwrap()
+			.lines()
+			.map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap()["timestamp"].as_u64().unwrap())
+			.collect()
+	}
+
+	#[tokio::test]
+	async fn command_receives_every_event(){
+		let path = temp_file("command");
+		send(&[command_sink(&path, 0)], &[event(1), event(2)]).await;
+
+		assert_eq!(received_timestamps(&path), vec![1, 2]);
+		let _ = std::fs::remove_file(&path);
+	}
+
+	#[tokio::test]
+	async fn failed_command_does_not_drop_the_next_events(){
+		let path = temp_file("failing");
+		send(&[command_sink(&path, 1)], &[event(1), event(2), event(3)]).await;
+
+		assert_eq!(received_timestamps(&path), vec![1, 2, 3]);
+		let _ = std::fs::remove_file(&path);
+	}
+
+	#[tokio::test]
+	async fn webhook_receives_every_event(){
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+		// Answers the first request with 500 and the second with 200
+		let server = thread::spawn(move || {
+			let mut bodies = Vec::new();
+			for (i, stream) in listener.incoming().take(2).enumerate() {
+				let mut reader = BufReader::new(stream.unwrap());
+				let mut length = 0;
+				loop {
+					let mut line = String::new();
+					reader.read_line(&mut line).unwrap();
+					if line == "\r\n" {
+						break;
+					}
+					if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+						length = value.trim().parse().unwrap();
+					}
+				}
+				let mut body = vec![0; length];
+				reader.read_exact(&mut body).unwrap();
+				bodies.push(serde_json::from_slice::<serde_json::Value>(&body).unwrap());
+
+				let status = if i == 0 { "500 Internal Server Error" } else { "200 OK" };
+				write!(reader.get_mut(), "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).unwrap();
+			}
+			bodies
+		});
+
+		send(&[Sink::Webhook { url }], &[event(1), event(2)]).await;
+
+		let bodies = server.join().unwrap();
+		let timestamps: Vec<u64> = bodies.iter().map(|b| b["timestamp"].as_u64().unwrap()).collect();
+		assert_eq!(timestamps, vec![1, 2]);
+		assert_eq!(bodies[0]["event"], "deleted_upstream");
+	}
+
+	#[tokio::test]
+	async fn smtp_receives_a_digest(){
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let port = listener.local_addr().unwrap().port();
+
+		// Accepts a single message, answering every command with success
+		let server = thread::spawn(move || {
+			let (stream, _) = listener.accept().unwrap();
+			let mut reader = BufReader::new(stream);
+			let mut data = String::new();
+			let mut in_data = false;
+			write!(reader.get_mut(), "220 localhost ESMTP\r\n").unwrap();
+			loop {
+				let mut line = String::new();
+				if reader.read_line(&mut line).unwrap() == 0 {
+					break;
+				}
+				let reply = if in_data {
+					if line != ".\r\n" {
+						data.push_str(&line);
+						continue;
+					}
+					in_data = false;
+					"250 Queued"
+				}else{
+					match line.get(..4).unwrap_or_default().to_ascii_uppercase().as_str() {
+						"DATA" => {
+							in_data = true;
+							"354 End data with <CR><LF>.<CR><LF>"
+						},
+						"QUIT" => {
+							write!(reader.get_mut(), "221 Bye\r\n").unwrap();
+							break;
+						},
+						_ => "250 OK"
+					}
+				};
+				write!(reader.get_mut(), "{}\r\n", reply).unwrap();
+			}
+			data
+		});
+
+		let smtp = SmtpSink {
+			host: String::from("127.0.0.1"),
+			port: Some(port),
+			tls: false,
+			username: None,
+			password: None,
+			from: String::from("archify@localhost"),
+			to: vec![String::from("me@localhost")]
+		};
+		send(&[Sink::Smtp(smtp)], &[event(1), event(2)]).await;
+
+		// Long lines of the quoted-printable body are split with soft line breaks
+		let data = server.join().unwrap().replace("=\r\n", "");
+		assert!(data.contains("Subject: Archify: 2 event(s)"), "{}", data);
+		assert!(data.contains(&event(1).summary()), "{}", data);
+		assert!(data.contains(&event(2).summary()), "{}", data);
+	}
+}
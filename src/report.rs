@@ -0,0 +1,16 @@
+use clap::ValueEnum;
+
+/// Output of a report: text for humans or JSON for scripts.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ReportFormat {
+	Text,
+	Json
+}
+
+/// Output of a time series.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SeriesFormat {
+	Table,
+	Csv,
+	Sparkline
+}
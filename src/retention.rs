@@ -0,0 +1,78 @@
+use crate::conf::*;
+
+use std::collections::HashSet;
+use std::time::{Duration, UNIX_EPOCH};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+/// Versions kept when pruning. A version is kept as soon as one rule keeps it, nothing is pruned when no rule is set.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RetentionPolicy {
+	/// Keep the last N versions.
+	pub keep_last: Option<u64>,
+	/// Keep versions younger than D days.
+	pub keep_days: Option<u64>,
+	/// Keep the latest version of each of the last N days having versions.
+	pub keep_daily: Option<u64>,
+	/// Keep the latest version of each of the last N weeks having versions.
+	pub keep_weekly: Option<u64>,
+	/// Keep the latest version of each of the last N months having versions.
+	pub keep_monthly: Option<u64>
+}
+
+impl RetentionPolicy {
+	pub fn is_empty(&self) -> bool {
+		self.keep_last.is_none()
+			&& self.keep_days.is_none()
+			&& self.keep_daily.is_none()
+			&& self.keep_weekly.is_none()
+			&& self.keep_monthly.is_none()
+	}
+}
+
+/// Keeps the latest version of each of the last `count` periods, `period` being the format naming the period of a date.
+fn keep_periods(timestamps: &[u64], count: Option<u64>, period: &str, kept: &mut HashSet<u64>){
+	let count = match count {
+		Some(c) => c as usize,
+		None => return
+	};
+	let mut periods: Vec<String> = Vec::new();
+
+	for timestamp in timestamps.iter().rev() {
+		let date = DateTime::<Local>::from(UNIX_EPOCH + Duration::from_secs(*timestamp));
+		let key = date.format(period).to_string();
+		if !periods.contains(&key) {
+			if periods.len() == count {
+				break;
+			}
+			periods.push(key);
+			kept.insert(*timestamp);
+		}
+	}
+}
+
+/// Timestamps of the versions to delete, `timestamps` being ascending.
+/// Pinned versions and the latest version, against which updates are compared, are always kept.
+pub fn get_pruned_timestamps(policy: &RetentionPolicy, timestamps: &[u64], pinned: &[u64], now: u64) -> Vec<u64>{
+	if policy.is_empty() {
+		return Vec::new();
+	}
+
+	let mut kept: HashSet<u64> = pinned.iter().copied().collect();
+	kept.extend(timestamps.last());
+
+	if let Some(last) = policy.keep_last {
+		kept.extend(timestamps.iter().rev().take(last as usize));
+	}
+	if let Some(days) = policy.keep_days {
+		let limit = now.saturating_sub(days * CONF_SECONDS_PER_DAY);
+		kept.extend(timestamps.iter().filter(|t| **t >= limit));
+	}
+	keep_periods(timestamps, policy.keep_daily, "%Y-%m-%d", &mut kept);
+	keep_periods(timestamps, policy.keep_weekly, "%G-W%V", &mut kept);
+	keep_periods(timestamps, policy.keep_monthly, "%Y-%m", &mut kept);
+
+	timestamps.iter().filter(|t| !kept.contains(t)).copied().collect()
+}
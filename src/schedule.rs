@@ -0,0 +1,56 @@
+use crate::conf::*;
+
+use std::str::FromStr;
+use std::time::{Duration, UNIX_EPOCH};
+
+use chrono::{DateTime, Local};
+
+/// When a playlist must be checked for a new version.
+pub enum Schedule {
+	/// Cron expression: sec min hour day-of-month month day-of-week [year], in local time.
+	Cron(Box<cron::Schedule>),
+	/// Seconds between two checks.
+	Interval(u64)
+}
+
+impl Schedule {
+	/// Parses a preset (see `CONF_SCHEDULE_PRESETS`), an interval such as `30m`, `6h`, `1d` or `1w`, or a cron expression.
+	pub fn parse(spec: &str) -> Result<Schedule, String> {
+		let spec = spec.trim();
+		if let Some((_, cron)) = CONF_SCHEDULE_PRESETS.iter().find(|(name, _)| *name == spec) {
+			return Schedule::parse(cron);
+		}
+
+		let unit = match spec.chars().last() {
+			Some('s') => Some(1),
+			Some('m') => Some(60),
+			Some('h') => Some(60 * 60),
+			Some('d') => Some(CONF_SECONDS_PER_DAY),
+			Some('w') => Some(7 * CONF_SECONDS_PER_DAY),
+			_ => None
+		};
+		if let Some(unit) = unit {
+			if let Ok(count) = spec[..spec.len() - 1].parse::<u64>() {
+				return match count.checked_mul(unit) {
+					Some(seconds) if seconds > 0 => Ok(Schedule::Interval(seconds)),
+					_ => Err(format!("{} is not a valid interval.", spec))
+				};
+			}
+		}
+
+		cron::Schedule::from_str(spec)
+			.map(|s| Schedule::Cron(Box::new(s)))
+			.map_err(|e| format!("{} is neither a preset, an interval nor a cron expression: {}", spec, e))
+	}
+
+	/// First time, after a check done at `last`, at which the playlist is due again. `u64::MAX` if it never is.
+	pub fn next_after(&self, last: u64) -> u64 {
+		match self {
+			Schedule::Interval(seconds) => last.saturating_add(*seconds),
+			Schedule::Cron(cron) => {
+				let last = DateTime::<Local>::from(UNIX_EPOCH + Duration::from_secs(last));
+				cron.after(&last).next().map_or(u64::MAX, |d| d.timestamp() as u64)
+			}
+		}
+	}
+}
@@ -0,0 +1,95 @@
+use crate::database::SearchHit;
+
+use std::collections::HashMap;
+
+use log::error;
+use rspotify::model::PlaylistId;
+use rspotify::prelude::Id;
+use serde::Serialize;
+
+/// Versions of a playlist containing a matching item.
+#[derive(Serialize)]
+pub struct PlaylistMatch {
+	pub playlist_id: String,
+	pub playlist_name: Option<String>,
+	/// Indexes as given by --tracked.
+	pub versions: Vec<u64>
+}
+
+#[derive(Serialize)]
+pub struct ItemMatch {
+	pub uri: String,
+	pub name: String,
+	pub artists: Vec<String>,
+	pub album: Option<String>,
+	pub playlists: Vec<PlaylistMatch>
+}
+
+/// Groups hits by item then by playlist, keeping the rank of the best hit of each item.
+/// `version_timestamps` gives the ascending timestamps of the versions of a playlist.
+/// Hits of versions no longer archived, left by a stale index, are skipped.
+pub fn group_hits(hits: Vec<SearchHit>, version_timestamps: impl Fn(&PlaylistId) -> Vec<u64>) -> Vec<ItemMatch> {
+	let mut matches: Vec<ItemMatch> = Vec::new();
+	// A query can hit every item of every version, so matches and versions are looked up by key
+	let mut match_index: HashMap<String, usize> = HashMap::new();
+	let mut timestamps: HashMap<String, Vec<u64>> = HashMap::new();
+	let mut stale = 0;
+
+	for hit in hits {
+		let playlist_id = hit.playlist_id.id().to_string();
+		let position = timestamps.entry(playlist_id.clone())
+			.or_insert_with(|| version_timestamps(&hit.playlist_id))
+			.binary_search(&hit.timestamp);
+		let index = match position {
+			Ok(position) => position as u64 + 1,
+			Err(_) => {
+				stale += 1;
+				continue;
+			}
+		};
+
+		let i = match match_index.get(&hit.uri) {
+			Some(i) => *i,
+			None => {
+				match_index.insert(hit.uri.clone(), matches.len());
+				matches.push(ItemMatch {
+					uri: hit.uri,
+					name: hit.name,
+					artists: hit.artists,
+					album: hit.album,
+					playlists: Vec::new()
+				});
+				matches.len() - 1
+			}
+		};
+
+		let playlists = &mut matches[i].playlists;
+		match playlists.iter_mut().find(|p| p.playlist_id == playlist_id) {
+			Some(p) => {
+				if !p.versions.contains(&index) {
+					p.versions.push(index);
+				}
+				// The latest name of the playlist is preferred
+				if hit.playlist_name.is_some() && p.versions.iter().all(|v| *v <= index) {
+					p.playlist_name = hit.playlist_name;
+				}
+			},
+			None => playlists.push(PlaylistMatch {
+				playlist_id,
+				playlist_name: hit.playlist_name,
+				versions: vec![index]
+			})
+		}
+	}
+
+	for m in &mut matches {
+		for p in &mut m.playlists {
+			p.versions.sort_unstable();
+		}
+	}
+	if stale > 0 {
+		error!("{} hit(s) refer to versions no longer archived, run `search --rebuild` to refresh the index", stale);
+	}
+
+	matches
+}
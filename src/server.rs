@@ -0,0 +1,382 @@
+use crate::archive::{self, Archive, VersionSummary};
+use crate::conf::*;
+use crate::web::{self, Links, ServerLinks, VersionDetail};
+use crate::feed::{self, FeedFormat};
+use crate::{database, diff, items, metrics};
+
+use std::collections::HashMap;
+
+use log::{error, info};
+use rspotify::model::PlaylistId;
+use rspotify::prelude::Id;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tokio::runtime::Runtime;
+use url::form_urlencoded;
+
+const TOKEN_COOKIE: &str = "archify_token";
+const LOGIN_PATH: &str = "/login";
+
+enum Body {
+	Json(Value),
+	Html(String),
+	/// Served inline, or as a download if `attachment`.
+	File { content_type: &'static str, filename: String, data: Vec<u8>, attachment: bool },
+	Redirect(String)
+}
+
+type Reply = (u16, Body);
+
+/// State kept across requests: the Spotify clients of the archive are only created by the first update or export.
+struct Context<'a> {
+	archive: &'a mut Archive,
+	runtime: Runtime
+}
+
+fn error(status: u16, message: &str) -> Reply {
+	(status, Body::Json(json!({ "error": message })))
+}
+
+fn to_reply(value: impl Serialize) -> Reply {
+	(200, Body::Json(serde_json::to_value(value).unwrap()))
+}
+
+/// Turns an error of the JSON API into a page for the web UI.
+fn to_page((status, body): Reply) -> Reply {
+	match body {
+		Body::Json(value) => (status, Body::Html(web::render_error(&ServerLinks, value["error"].as_str().unwrap_or_default()))),
+		body => (status, body)
+	}
+}
+
+fn playlist_id(segment: &str) -> Result<PlaylistId<'static>, Reply> {
+	archive::parse_playlist_id(segment).ok_or_else(|| error(400, "Invalid playlist id."))
+}
+
+fn version(ctx: &Context, p_id: &PlaylistId, segment: &str) -> Result<(u64, database::Playlist), Reply> {
+	let version = database::Version::parse(segment).ok_or_else(|| error(400, "Invalid version."))?;
+	ctx.runtime.block_on(ctx.archive.version(p_id, &version)).ok_or_else(|| error(404, "No such version."))
+}
+
+fn get_versions(ctx: &Context, p_id: &PlaylistId) -> Result<Vec<VersionSummary>, Reply> {
+	let versions = ctx.runtime.block_on(ctx.archive.versions(p_id));
+	match versions.is_empty() {
+		true => Err(error(404, "No version recorded for this playlist.")),
+		false => Ok(versions)
+	}
+}
+
+fn get_diff(ctx: &Context, p_id: &PlaylistId, query: &HashMap<String, String>) -> Result<(u64, u64, diff::Diff), Reply> {
+	let from = query.get("from").ok_or_else(|| error(400, "Missing from."))?;
+	let to = query.get("to").ok_or_else(|| error(400, "Missing to."))?;
+	let (from, old) = version(ctx, p_id, from)?;
+	let (to, new) = version(ctx, p_id, to)?;
+
+	let d = diff::diff(
+		&old.data.as_ref().map(items::get_items).unwrap_or_default(),
+		&new.data.as_ref().map(items::get_items).unwrap_or_default()
+	);
+	Ok((from, to, d))
+}
+
+fn add_playlist(ctx: &Context, body: &str) -> Result<Reply, Reply> {
+	let body: Value = serde_json::from_str(body).map_err(|_| error(400, "Body must be JSON."))?;
+	let playlist = body["playlist"].as_str().ok_or_else(|| error(400, "Missing playlist."))?;
+	let p_id = ctx.runtime.block_on(ctx.archive.track(playlist)).map_err(|e| error(400, &e))?;
+	Ok((201, Body::Json(json!({ "id": p_id.id() }))))
+}
+
+fn delete_playlist(ctx: &Context, p_id: &PlaylistId, query: &HashMap<String, String>) -> Reply {
+	let hard = query.get("hard").map(String::as_str) == Some("true");
+	ctx.runtime.block_on(ctx.archive.untrack(p_id.id(), hard)).unwrap();
+	to_reply(json!({ "id": p_id.id() }))
+}
+
+fn get_items(ctx: &Context, p_id: &PlaylistId, segment: &str) -> Result<Reply, Reply> {
+	let (index, p) = version(ctx, p_id, segment)?;
+	Ok(to_reply(json!({
+		"index": index,
+		"timestamp": p.timestamp,
+		"items": p.data.as_ref().map(items::get_items).unwrap_or_default()
+	})))
+}
+
+fn update(ctx: &mut Context, query: &HashMap<String, String>) -> Reply {
+	let force = query.get("force").map(String::as_str) == Some("true");
+	// Auto-export needs the user to authorize archify, which only the CLI can ask for
+	let auto_export = ctx.archive.due_playlists(force).iter().any(|p| ctx.archive.db().get_auto_export(&p.id).is_some());
+	if auto_export && !ctx.archive.can_export() {
+		return error(409, "Auto-export needs a Spotify user token: export a playlist with the CLI once to authorize archify.");
+	}
+	let _lock = match archive::lock_updates() {
+		Some(lock) => lock,
+		None => return error(409, "Another archify process is updating the playlists.")
+	};
+	match ctx.runtime.block_on(ctx.archive.update(force)) {
+		Ok(events) => to_reply(events),
+		Err(e) => error(502, &e)
+	}
+}
+
+fn version_page(ctx: &Context, p_id: &PlaylistId, segment: &str) -> Result<Reply, Reply> {
+	let (index, p) = version(ctx, p_id, segment)?;
+	let data = p.data.as_ref().ok_or_else(|| error(404, "No such version."))?;
+	let items = items::get_items(data);
+	let latest = ctx.archive.db().get_version_index(p_id, &database::Version::Latest).unwrap_or(index);
+
+	Ok((200, Body::Html(web::render_version(&ServerLinks, &VersionDetail {
+		id: p_id.id(),
+		index,
+		timestamp: p.timestamp,
+		name: &data.name,
+		description: data.description.as_deref(),
+		owner: data.owner.display_name.as_deref().unwrap_or(data.owner.id.id()),
+		followers: data.followers.total,
+		cover: p.cover.is_some(),
+		items: &items,
+		latest: index == latest
+	}))))
+}
+
+fn cover(ctx: &Context, p_id: &PlaylistId, segment: &str) -> Result<Reply, Reply> {
+	let (index, p) = version(ctx, p_id, segment)?;
+	let data = p.cover.ok_or_else(|| error(404, "No cover archived for this version."))?;
+	Ok((200, Body::File { content_type: "image/jpeg", filename: format!("{}-{}.jpg", p_id.id(), index), data, attachment: false }))
+}
+
+fn download(ctx: &Context, p_id: &PlaylistId, segment: &str, query: &HashMap<String, String>) -> Result<Reply, Reply> {
+	let (index, p) = version(ctx, p_id, segment)?;
+	let items = p.data.as_ref().map(items::get_items).unwrap_or_default();
+
+	let mut data = Vec::new();
+	match query.get("format").map(String::as_str) {
+		Some("json") => {
+			serde_json::to_writer_pretty(&mut data, &items).unwrap();
+			Ok((200, Body::File { content_type: "application/json", filename: format!("{}-{}.json", p_id.id(), index), data, attachment: true }))
+		},
+		Some("csv") | None => {
+			items::write_csv(&items, &mut data).unwrap();
+			Ok((200, Body::File { content_type: "text/csv", filename: format!("{}-{}.csv", p_id.id(), index), data, attachment: true }))
+		},
+		Some(_) => Err(error(400, "Format must be csv or json."))
+	}
+}
+
+fn export(ctx: &mut Context, p_id: &PlaylistId, segment: &str) -> Result<Reply, Reply> {
+	let (index, p) = version(ctx, p_id, segment)?;
+	ctx.runtime.block_on(ctx.archive.export_version(&p, index, CONF_DEFAULT_EXPORT_TEMPLATE)).map_err(|e| error(409, &e))?;
+	Ok((303, Body::Redirect(ServerLinks.version(p_id.id(), index))))
+}
+
+fn get_feed(ctx: &Context, file: &str) -> Result<Reply, Reply> {
+	let (name, format) = file.rsplit_once('.')
+		.and_then(|(name, extension)| FeedFormat::from_extension(extension).map(|format| (name, format)))
+		.ok_or_else(|| error(404, "Feeds end with .atom or .rss."))?;
+
+	let feed = match name {
+		CONF_FEED_GLOBAL_NAME => feed::get_global_feed(ctx.archive.db()),
+		name => feed::get_playlist_feed(ctx.archive.db(), &playlist_id(name)?).ok_or_else(|| error(404, "No version recorded for this playlist."))?
+	};
+	Ok((200, Body::File {
+		content_type: format.content_type(),
+		filename: String::from(file),
+		data: feed::render(&feed, format).into_bytes(),
+		attachment: false
+	}))
+}
+
+fn route_api(ctx: &mut Context, method: &Method, segments: &[&str], query: &HashMap<String, String>, body: &str) -> Result<Reply, Reply> {
+	match (method, segments) {
+		(Method::Get, ["playlists"]) => Ok(to_reply(ctx.runtime.block_on(ctx.archive.list()))),
+		(Method::Post, ["playlists"]) => add_playlist(ctx, body),
+		(Method::Delete, ["playlists", id]) => Ok(delete_playlist(ctx, &playlist_id(id)?, query)),
+		(Method::Get, ["playlists", id, "versions"]) => Ok(to_reply(get_versions(ctx, &playlist_id(id)?)?)),
+		(Method::Get, ["playlists", id, "versions", v]) => get_items(ctx, &playlist_id(id)?, v),
+		(Method::Get, ["playlists", id, "diff"]) => {
+			let (from, to, d) = get_diff(ctx, &playlist_id(id)?, query)?;
+			Ok(to_reply(json!({ "from": from, "to": to, "added": d.added, "removed": d.removed })))
+		},
+		(Method::Post, ["update"]) => Ok(update(ctx, query)),
+		(Method::Get, ["feeds", file]) => get_feed(ctx, file),
+		(Method::Get, ["metrics"]) => Ok((200, Body::File {
+			content_type: "text/plain; version=0.0.4",
+			filename: String::from("metrics"),
+			data: metrics::render(ctx.archive.db()).into_bytes(),
+			attachment: false
+		})),
+		_ => Err(error(404, "Unknown endpoint."))
+	}
+}
+
+fn route_ui(ctx: &mut Context, method: &Method, segments: &[&str], query: &HashMap<String, String>) -> Result<Reply, Reply> {
+	match (method, segments) {
+		(Method::Get, ["style.css"]) => Ok((200, Body::File {
+			content_type: "text/css",
+			filename: String::from("style.css"),
+			data: web::STYLE.as_bytes().to_vec(),
+			attachment: false
+		})),
+		(Method::Get, [""]) => Ok((200, Body::Html(web::render_index(&ServerLinks, &ctx.runtime.block_on(ctx.archive.list()))))),
+		(Method::Get, ["ui", "playlists", id]) => {
+			let p_id = playlist_id(id)?;
+			Ok((200, Body::Html(web::render_timeline(&ServerLinks, p_id.id(), &get_versions(ctx, &p_id)?))))
+		},
+		(Method::Get, ["ui", "playlists", id, "versions", v]) => version_page(ctx, &playlist_id(id)?, v),
+		(Method::Get, ["ui", "playlists", id, "versions", v, "cover"]) => cover(ctx, &playlist_id(id)?, v),
+		(Method::Get, ["ui", "playlists", id, "versions", v, "download"]) => download(ctx, &playlist_id(id)?, v, query),
+		(Method::Post, ["ui", "playlists", id, "versions", v, "export"]) => export(ctx, &playlist_id(id)?, v),
+		(Method::Get, ["ui", "playlists", id, "diff"]) => {
+			let p_id = playlist_id(id)?;
+			let versions = get_versions(ctx, &p_id)?;
+			let (from, to, d) = get_diff(ctx, &p_id, query)?;
+			Ok((200, Body::Html(web::render_diff(&ServerLinks, p_id.id(), &versions, from, to, &d))))
+		},
+		_ => Err(error(404, "Unknown page."))
+	}
+}
+
+fn is_ui(path: &str) -> bool {
+	matches!(path.trim_matches('/').split('/').next(), Some("") | Some("ui") | Some("style.css") | Some("login"))
+}
+
+fn route(ctx: &mut Context, method: &Method, path: &str, query: &HashMap<String, String>, body: &str) -> Reply {
+	let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+	match is_ui(path) {
+		true => route_ui(ctx, method, &segments, query).unwrap_or_else(to_page),
+		false => route_api(ctx, method, &segments, query, body).unwrap_or_else(|e| e)
+	}
+}
+
+fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+	request.headers().iter().find(|h| h.field.equiv(name)).map(|h| h.value.as_str())
+}
+
+/// Guards the POST requests against cross-site requests: those of the web UI must come from one of its pages, those
+/// of the API must be JSON, which a form of another site cannot send.
+fn check_post(request: &Request, ui: bool) -> Result<(), Reply> {
+	if ui {
+		let host = header(request, "Host");
+		let origin = header(request, "Origin").map(|o| o.trim_start_matches("http://").trim_start_matches("https://"));
+		match host.is_some() && origin == host {
+			true => Ok(()),
+			false => Err(to_page(error(403, "Forms must be sent from the pages of archify.")))
+		}
+	}else{
+		let content_type = header(request, "Content-Type").and_then(|c| c.split(';').next()).map(str::trim);
+		match content_type.is_some_and(|c| c.eq_ignore_ascii_case("application/json")) {
+			true => Ok(()),
+			false => Err(error(415, "Content-Type must be application/json."))
+		}
+	}
+}
+
+/// Compares in a time that does not depend on where `a` and `b` differ, so that the token cannot be guessed byte by byte.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+	a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// The token is taken from the Authorization header, or from the cookie set by the login form.
+fn is_authorized(request: &Request, token: &Option<String>) -> bool {
+	let token = match token {
+		Some(token) => token,
+		None => return true
+	};
+
+	request.headers().iter().any(|h| {
+		(h.field.equiv("Authorization") && h.value.as_str().strip_prefix("Bearer ").is_some_and(|t| constant_time_eq(t, token)))
+			|| (h.field.equiv("Cookie") && h.value.as_str().split(';').any(|c| {
+				c.trim().strip_prefix(TOKEN_COOKIE).and_then(|c| c.strip_prefix('=')).is_some_and(|t| constant_time_eq(t, token))
+			}))
+	})
+}
+
+fn handle(ctx: &mut Context, mut request: Request, token: &Option<String>){
+	let method = request.method().clone();
+	let (path, query) = match request.url().split_once('?') {
+		Some((path, query)) => (String::from(path), String::from(query)),
+		None => (String::from(request.url()), String::new())
+	};
+	let query: HashMap<String, String> = form_urlencoded::parse(query.as_bytes()).into_owned().collect();
+
+	let checked = match method {
+		Method::Post => check_post(&request, is_ui(&path)),
+		_ => Ok(())
+	};
+
+	let mut body = String::new();
+	let mut logged_in = false;
+	let (status, body) = if let Err(reply) = checked {
+		reply
+	}else if request.as_reader().read_to_string(&mut body).is_err() {
+		error(400, "Body cannot be read.")
+	}else if path == LOGIN_PATH {
+		match (&method, token) {
+			(Method::Post, Some(token)) => {
+				let form: HashMap<String, String> = form_urlencoded::parse(body.as_bytes()).into_owned().collect();
+				logged_in = form.get("token").is_some_and(|t| constant_time_eq(t, token));
+				match logged_in {
+					true => (303, Body::Redirect(ServerLinks.home())),
+					false => (401, Body::Html(web::render_login(&ServerLinks, LOGIN_PATH, Some("Invalid token."))))
+				}
+			},
+			(Method::Get, Some(_)) => (200, Body::Html(web::render_login(&ServerLinks, LOGIN_PATH, None))),
+			(_, None) => (303, Body::Redirect(ServerLinks.home())),
+			_ => to_page(error(404, "Unknown page."))
+		}
+	}else if path != "/style.css" && !is_authorized(&request, token) {
+		match is_ui(&path) {
+			true => (303, Body::Redirect(String::from(LOGIN_PATH))),
+			false => error(401, "Missing or invalid bearer token.")
+		}
+	}else{
+		route(ctx, &method, &path, &query, &body)
+	};
+	info!("{} {} - {}", method, path, status);
+
+	let mut response = match body {
+		Body::Json(value) => Response::from_string(value.to_string())
+			.with_header("Content-Type: application/json".parse::<Header>().unwrap()),
+		Body::Html(html) => Response::from_string(html)
+			.with_header("Content-Type: text/html; charset=utf-8".parse::<Header>().unwrap()),
+		Body::File { content_type, filename, data, attachment } => Response::from_data(data)
+			.with_header(Header::from_bytes("Content-Type", content_type).unwrap())
+			.with_header(Header::from_bytes(
+				"Content-Disposition",
+				format!("{}; filename=\"{}\"", if attachment { "attachment" } else { "inline" }, filename)
+			).unwrap()),
+		Body::Redirect(location) => Response::from_data(Vec::new())
+			.with_header(Header::from_bytes("Location", location).unwrap())
+	}.with_status_code(status);
+
+	if let (Some(token), true) = (token, logged_in) {
+		response.add_header(Header::from_bytes("Set-Cookie", format!("{}={}; Path=/; HttpOnly; SameSite=Strict", TOKEN_COOKIE, token)).unwrap());
+	}
+	if let Err(e) = request.respond(response) {
+		error!("Response cannot be sent: {}", e);
+	}
+}
+
+/// Serves the archive over HTTP until the process is stopped: a JSON API and a web UI browsing the local database.
+/// Requests are handled one at a time.
+/// Every request must carry `token`, if any, as a bearer token or the cookie set by the login form of the web UI.
+pub fn run(archive: &mut Archive, address: &str, token: Option<String>){
+	let server = match Server::http(address) {
+		Ok(server) => server,
+		Err(e) => {
+			error!("Cannot listen on {}: {}", address, e);
+			return;
+		}
+	};
+	info!("Listening on http://{}.", address);
+
+	let mut ctx = Context {
+		archive,
+		runtime: Runtime::new().unwrap()
+	};
+
+	for request in server.incoming_requests() {
+		handle(&mut ctx, request, &token);
+	}
+}
@@ -0,0 +1,235 @@
+use crate::archive::{self, Archive, PlaylistSummary, VersionSummary};
+use crate::web::{self, Links, VersionDetail};
+use crate::{database, diff, items};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use log::{error, info};
+use rspotify::prelude::Id;
+use sha2::{Digest, Sha256};
+
+const MANIFEST: &str = ".archify-site.json";
+
+/// Links relative to a page `root` away from the top of the site, so that it can be hosted under any path.
+struct SiteLinks {
+	root: &'static str
+}
+
+impl Links for SiteLinks {
+	fn home(&self) -> String {
+		format!("{}index.html", self.root)
+	}
+
+	fn stylesheet(&self) -> String {
+		format!("{}style.css", self.root)
+	}
+
+	fn playlist(&self, id: &str) -> String {
+		format!("{}playlists/{}/index.html", self.root, id)
+	}
+
+	fn version(&self, id: &str, index: u64) -> String {
+		format!("{}playlists/{}/{}/index.html", self.root, id, index)
+	}
+
+	fn cover(&self, id: &str, index: u64) -> String {
+		format!("{}playlists/{}/{}/cover.jpg", self.root, id, index)
+	}
+
+	fn download(&self, id: &str, index: u64, format: &str) -> String {
+		format!("{}playlists/{}/{}/items.{}", self.root, id, index, format)
+	}
+
+	fn diff(&self, id: &str, from: u64, to: u64) -> String {
+		format!("{}playlists/{}/diff/{}-{}.html", self.root, id, from, to)
+	}
+
+	fn export(&self, _id: &str, _index: u64) -> Option<String> {
+		None
+	}
+
+	fn diff_form(&self, _id: &str) -> Option<String> {
+		None
+	}
+}
+
+// Pages are rendered with the links matching their depth
+const INDEX_LINKS: SiteLinks = SiteLinks { root: "" };
+const PLAYLIST_LINKS: SiteLinks = SiteLinks { root: "../../" };
+const VERSION_LINKS: SiteLinks = SiteLinks { root: "../../../" };
+
+fn fingerprint(parts: &[&[u8]]) -> String {
+	let mut hasher = Sha256::new();
+	hasher.update(env!("CARGO_PKG_VERSION"));
+	for part in parts {
+		hasher.update((part.len() as u64).to_le_bytes());
+		hasher.update(part);
+	}
+	format!("{:x}", hasher.finalize())
+}
+
+/// Files of the site with the fingerprint of what they were rendered from, to only write the ones that changed.
+struct Site {
+	dir: PathBuf,
+	previous: HashMap<String, String>,
+	current: HashMap<String, String>,
+	written: usize,
+	unchanged: usize,
+	failed: usize
+}
+
+impl Site {
+	fn open(dir: &str) -> Site {
+		let dir = PathBuf::from(dir);
+		let previous = fs::read_to_string(dir.join(MANIFEST))
+			.ok()
+			.and_then(|manifest| serde_json::from_str(&manifest).ok())
+			.unwrap_or_default();
+
+		Site { dir, previous, current: HashMap::new(), written: 0, unchanged: 0, failed: 0 }
+	}
+
+	/// Writes the file at `path` unless it was already rendered from the same `fingerprint`.
+	fn write_with(&mut self, path: String, fingerprint: String, render: impl FnOnce() -> Vec<u8>){
+		let file = self.dir.join(&path);
+		if self.previous.get(&path) == Some(&fingerprint) && file.exists() {
+			self.unchanged += 1;
+		}else{
+			if let Err(e) = fs::create_dir_all(file.parent().unwrap()).and_then(|_| fs::write(&file, render())) {
+				error!("Site file {} cannot be written: {}", file.display(), e);
+				self.failed += 1;
+				// Kept with its previous fingerprint, so that the next build writes it again instead of removing it
+				if let Some(previous) = self.previous.get(&path).cloned() {
+					self.current.insert(path, previous);
+				}
+				return;
+			}
+			self.written += 1;
+		}
+		self.current.insert(path, fingerprint);
+	}
+
+	/// Writes a page cheap to render, which is its own fingerprint.
+	fn write(&mut self, path: String, content: String){
+		let fingerprint = fingerprint(&[content.as_bytes()]);
+		self.write_with(path, fingerprint, || content.into_bytes());
+	}
+
+	/// Removes the files of playlists and versions not in the archive anymore, then saves the manifest.
+	fn finish(self) -> usize {
+		let mut removed = 0;
+		let mut dirs = Vec::new();
+		for path in self.previous.keys().filter(|path| !self.current.contains_key(*path)) {
+			let file = self.dir.join(path);
+			if fs::remove_file(&file).is_ok() {
+				removed += 1;
+				dirs.extend(file.ancestors().skip(1).take_while(|d| *d != self.dir).map(PathBuf::from));
+			}
+		}
+		// Deepest first, only the directories left empty are removed
+		dirs.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
+		for d in dirs {
+			let _ = fs::remove_dir(d);
+		}
+
+		if let Err(e) = fs::write(self.dir.join(MANIFEST), serde_json::to_string_pretty(&self.current).unwrap()) {
+			error!("Site manifest cannot be written, the next build writes every file again: {}", e);
+		}
+		removed
+	}
+}
+
+/// Writes the pages of a playlist. `false` if it has none, so that the index does not link to it.
+fn build_playlist(db: &dyn database::Storage, site: &mut Site, summary: &mut PlaylistSummary) -> bool{
+	let p_id = match archive::parse_playlist_id(&summary.id) {
+		Some(p_id) => p_id,
+		None => return false
+	};
+	let id = p_id.id();
+	let versions = db.get_all_tracked_versions(&p_id);
+	// Versions without content have no page, the timeline and diffs do not link to them
+	let with_data: Vec<u64> = versions.iter().filter(|p| p.data.is_some()).map(|p| p.count).collect();
+	let summaries: Vec<VersionSummary> = archive::summarize_versions(db, &p_id, &versions)
+		.into_iter()
+		.filter(|v| with_data.contains(&v.index))
+		.collect();
+	if summaries.is_empty() {
+		return false;
+	}
+	// The index shows the cover of the latest version, only written with its page
+	summary.cover &= with_data.contains(&summary.versions);
+
+	site.write(format!("playlists/{}/index.html", id), web::render_timeline(&PLAYLIST_LINKS, id, &summaries));
+
+	let versions: Vec<&database::Playlist> = versions.iter().filter(|p| p.count != 0).collect();
+	let latest = versions.len() as u64;
+	for (i, p) in versions.iter().enumerate() {
+		let index = i as u64 + 1;
+		let data = match &p.data {
+			Some(data) => data,
+			None => continue
+		};
+		let dir = format!("playlists/{}/{}", id, index);
+		let cover_sha256 = p.cover_sha256.unwrap_or_default();
+		// After a prune, an index can hold another version with the same content but its own date and items
+		let timestamp = p.timestamp.to_be_bytes();
+
+		site.write_with(format!("{}/index.html", dir), fingerprint(&[&p.sha256, &timestamp, &cover_sha256, &[(index == latest) as u8]]), || {
+			web::render_version(&VERSION_LINKS, &VersionDetail {
+				id,
+				index,
+				timestamp: p.timestamp,
+				name: &data.name,
+				description: data.description.as_deref(),
+				owner: data.owner.display_name.as_deref().unwrap_or(data.owner.id.id()),
+				followers: data.followers.total,
+				cover: p.cover_sha256.is_some(),
+				items: &items::get_items(data),
+				latest: index == latest
+			}).into_bytes()
+		});
+		site.write_with(format!("{}/items.csv", dir), fingerprint(&[&p.sha256, &timestamp]), || {
+			let mut csv = Vec::new();
+			items::write_csv(&items::get_items(data), &mut csv).unwrap();
+			csv
+		});
+		site.write_with(format!("{}/items.json", dir), fingerprint(&[&p.sha256, &timestamp]), || {
+			serde_json::to_vec_pretty(&items::get_items(data)).unwrap()
+		});
+		if let Some(sha256) = &p.cover_sha256 {
+			site.write_with(format!("{}/cover.jpg", dir), fingerprint(&[sha256]), || {
+				db.get_cover(sha256).unwrap_or_default()
+			});
+		}
+
+		if index > 1 {
+			let old = versions[i - 1];
+			site.write_with(format!("playlists/{}/diff/{}-{}.html", id, index - 1, index), fingerprint(&[&old.sha256, &old.timestamp.to_be_bytes(), &p.sha256, &timestamp]), || {
+				let d = diff::diff(&old.data.as_ref().map(items::get_items).unwrap_or_default(), &items::get_items(data));
+				web::render_diff(&VERSION_LINKS, id, &summaries, index - 1, index, &d).into_bytes()
+			});
+		}
+	}
+	true
+}
+
+/// Renders the archive as a static site in `dir`. Versions, their files and diffs are only rewritten when they changed
+/// since the previous build, pages of what left the archive are removed.
+pub async fn build(archive: &Archive, dir: &str){
+	if let Err(e) = fs::create_dir_all(dir) {
+		error!("Site directory {} cannot be created: {}", dir, e);
+		return;
+	}
+	let mut site = Site::open(dir);
+
+	let mut playlists = archive.list().await;
+	site.write(String::from("style.css"), String::from(web::STYLE));
+	playlists.retain_mut(|summary| build_playlist(archive.db(), &mut site, summary));
+	site.write(String::from("index.html"), web::render_index(&INDEX_LINKS, &playlists));
+
+	let (written, unchanged, failed) = (site.written, site.unchanged, site.failed);
+	let removed = site.finish();
+	info!("Site built in {}: {} file(s) written, {} unchanged, {} removed, {} failed.", dir, written, unchanged, removed, failed);
+}
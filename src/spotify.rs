@@ -1,14 +1,17 @@
 use crate::ArchifyConf;
 use crate::conf::*;
-use crate::database::Playlist;
+use crate::cover;
+use crate::items;
+use crate::metrics;
+use crate::database::{Playlist, PlaylistMetadata};
 
-use std::env;
+use std::future::Future;
 use std::path::PathBuf;
-use std::time::Duration;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::engine::{general_purpose, Engine};
+use chrono::{DateTime, Local};
 
-use chrono::DateTime;
-use chrono::Local;
 use log::error;
 use log::info;
 use rspotify::Token;
@@ -16,19 +19,14 @@ use rspotify::{AuthCodeSpotify, OAuth};
 use rspotify::model::{PlaylistId, PlayableItem};
 use rspotify::prelude::{BaseClient,Id, OAuthClient, PlayableId};
 use rspotify::scopes;
-use rspotify::{Credentials, ClientCredsSpotify, Config, DEFAULT_API_PREFIX, DEFAULT_PAGINATION_CHUNKS};
+use rspotify::http::HttpError;
+use rspotify::{Credentials, ClientCredsSpotify, ClientError, ClientResult, Config, DEFAULT_API_PREFIX, DEFAULT_PAGINATION_CHUNKS};
 use sha2::{Digest, Sha256};
 
 
 
-pub async fn get_spotify_client_from_client_credentials(app_conf: &ArchifyConf) -> ClientCredsSpotify{
-	env::set_var(RSPOTIFY_ENV_CLIENT_ID, &app_conf.archify_id);
-	env::set_var(RSPOTIFY_ENV_CLIENT_SECRET, &app_conf.archify_secret);
-
-	let creds = Credentials::from_env().unwrap();
-
-	env::remove_var(RSPOTIFY_ENV_CLIENT_ID);
-	env::remove_var(RSPOTIFY_ENV_CLIENT_SECRET);
+pub async fn get_spotify_client_from_client_credentials(app_conf: &ArchifyConf) -> ClientResult<ClientCredsSpotify>{
+	let creds = Credentials::new(&app_conf.archify_id, &app_conf.archify_secret);
 
 	let mut path = PathBuf::new();
 	path.push(RSPOTIFY_CLIENT_TOKEN_PATH);
@@ -46,7 +44,7 @@ pub async fn get_spotify_client_from_client_credentials(app_conf: &ArchifyConf)
 	let spot_client = ClientCredsSpotify::with_config(creds, config);
 
 	let tok = match token_exists {
-		true => spot_client.read_token_cache().await.unwrap(),
+		true => spot_client.read_token_cache().await?,
 		false => None
 	};
 
@@ -57,15 +55,16 @@ pub async fn get_spotify_client_from_client_credentials(app_conf: &ArchifyConf)
 		}
 			,
 		None => {
-			spot_client.request_token().await.unwrap();
+			metrics::inc_api_calls();
+			spot_client.request_token().await?;
 			info!("Client token NOT cached. Retreived from Spotify API.");
 		}
 	}
 
-	spot_client
+	Ok(spot_client)
 }
 
-pub async fn get_spotify_client_from_user(app_conf: &ArchifyConf) -> AuthCodeSpotify{
+fn get_user_client(app_conf: &ArchifyConf) -> AuthCodeSpotify{
 	let creds = Credentials::new(&app_conf.archify_id, &app_conf.archify_secret);
 
 	let oauth = OAuth{
@@ -85,19 +84,91 @@ pub async fn get_spotify_client_from_user(app_conf: &ArchifyConf) -> AuthCodeSpo
 		..Default::default()
 	};
 
-	let client = AuthCodeSpotify::with_config(creds, oauth, config);
+	AuthCodeSpotify::with_config(creds, oauth, config)
+}
+
+/// Client of the user, asking them on stdin to authorize archify if no usable token is cached.
+pub async fn get_spotify_client_from_user(app_conf: &ArchifyConf) -> ClientResult<AuthCodeSpotify>{
+	match get_cached_user_client(app_conf).await {
+		Ok(Some(client)) => return Ok(client),
+		Ok(None) => (),
+		Err(e) => error!("Cached user token cannot be used: {}", e)
+	}
 
-	let url = client.get_authorize_url(false).unwrap();
-	client.prompt_for_token(&url).await.unwrap();
-	client.refresh_token().await.unwrap();
-	client.write_token_cache().await.unwrap();
+	let client = get_user_client(app_conf);
 
-	client
+	let url = client.get_authorize_url(false)?;
+	metrics::inc_api_calls();
+	client.prompt_for_token(&url).await?;
+	client.write_token_cache().await?;
 
+	Ok(client)
 }
 
-pub async fn get_public_playlists(client: &ClientCredsSpotify, playlist_id: &PlaylistId<'static>) -> Playlist {
-	let fplaylist = client.playlist(playlist_id.clone_static(), None, None).await.unwrap();
+/// Whether the user authorized archify, caching a token.
+pub fn is_user_token_cached() -> bool {
+	PathBuf::from(RSPOTIFY_USER_TOKEN_PATH).exists()
+}
+
+/// Client of the user from the token cached when they authorized archify, refreshed if it expired. `None` if they
+/// never did: unlike `get_spotify_client_from_user`, this never prompts.
+pub async fn get_cached_user_client(app_conf: &ArchifyConf) -> ClientResult<Option<AuthCodeSpotify>>{
+	if !is_user_token_cached() {
+		return Ok(None);
+	}
+
+	let client = get_user_client(app_conf);
+
+	let token = match client.read_token_cache(true).await? {
+		Some(token) => token,
+		None => return Ok(None)
+	};
+	let expired = token.is_expired();
+	*client.get_token().lock().await.unwrap() = Some(token);
+	if expired {
+		metrics::inc_api_calls();
+		client.refresh_token().await?;
+	}
+
+	Ok(Some(client))
+}
+
+/// Sends `request` again when the API answers 429 Too Many Requests, after the time it asks to wait, or a server error.
+async fn with_retries<T, F: Future<Output = ClientResult<T>>>(mut request: impl FnMut() -> F) -> ClientResult<T> {
+	let mut retries = 0;
+	loop {
+		metrics::inc_api_calls();
+		let e = match request().await {
+			Err(ClientError::Http(e)) => e,
+			res => return res
+		};
+
+		let wait = match e.as_ref() {
+			HttpError::StatusCode(r) if r.status() == reqwest::StatusCode::TOO_MANY_REQUESTS => {
+				metrics::inc_rate_limited();
+				r.headers()
+					.get(reqwest::header::RETRY_AFTER)
+					.and_then(|v| v.to_str().ok())
+					.and_then(|v| v.parse::<u64>().ok())
+					.unwrap_or(CONF_API_RETRY_DELAY)
+					.min(CONF_API_MAX_RETRY_AFTER)
+			},
+			HttpError::StatusCode(r) if r.status().is_server_error() => CONF_API_RETRY_DELAY,
+			_ => return Err(ClientError::Http(e))
+		};
+		if retries == CONF_API_MAX_RETRIES {
+			return Err(ClientError::Http(e));
+		}
+
+		retries += 1;
+		metrics::inc_retries();
+		error!("Spotify API answered {}, retry {}/{} in {}s.", e, retries, CONF_API_MAX_RETRIES, wait);
+		tokio::time::sleep(Duration::from_secs(wait)).await;
+	}
+}
+
+pub async fn get_public_playlists(client: &ClientCredsSpotify, playlist_id: &PlaylistId<'static>) -> ClientResult<Playlist> {
+	let fplaylist = with_retries(|| client.playlist(playlist_id.clone_static(), None, None)).await?;
 
 	info!("Playlist {playlist_id} retreived, with {} tracks", fplaylist.tracks.items.len());
 	// To verbose
@@ -117,17 +188,11 @@ pub async fn get_public_playlists(client: &ClientCredsSpotify, playlist_id: &Pla
 		match &item.track {
 			Some(pi) => {
 				match pi {
-					PlayableItem::Episode(_) => {
-						let id = playlist_id.id();
-						info!("Untracked Podcast in {id}");
-					},
+					PlayableItem::Episode(episode) => hasher.update(episode.id.uri().as_bytes()),
 					PlayableItem::Track(track) => {
 						match &track.id {
 							Some(id) => hasher.update(id.id().as_bytes()),
-							None => {
-								let id = playlist_id.id();
-								info!("Untracked NON null and local item in {id}");
-							} 
+							None => hasher.update(items::local_fingerprint(track).as_bytes())
 						}
 					}
 				}
@@ -144,44 +209,138 @@ pub async fn get_public_playlists(client: &ClientCredsSpotify, playlist_id: &Pla
 		}
 	}
 
+	let tracks_sha256: [u8; 32] = hasher.finalize().into();
+
+	// A cover that cannot be downloaded fails the update, rather than making a version without it
+	let cover = match fplaylist.images.first() {
+		Some(image) => Some(get_playlist_cover(&image.url).await?),
+		None => None
+	};
+	let cover_sha256: Option<[u8; 32]> = cover.as_ref().map(|c| Sha256::digest(c).into());
+
+	let metadata = PlaylistMetadata {
+		name: fplaylist.name.clone(),
+		description: fplaylist.description.clone(),
+		owner_id: fplaylist.owner.id.id().to_string(),
+		public: fplaylist.public,
+		collaborative: fplaylist.collaborative,
+		followers: fplaylist.followers.total
+	};
+
+	// Followers are left out, they change between most updates and are recorded as observations instead
+	let mut hasher = Sha256::new();
+	hasher.update(tracks_sha256);
+	if let Some(c) = cover_sha256 {
+		hasher.update(c);
+	}
+	for field in [Some(metadata.name.as_str()), metadata.description.as_deref(), Some(metadata.owner_id.as_str())] {
+		hasher.update(field.unwrap_or_default().as_bytes());
+		hasher.update([0]);
+	}
+	hasher.update([metadata.public.map_or(2, u8::from), u8::from(metadata.collaborative)]);
+
 	let sha256 = hasher.finalize();
 
-	let playlist  = Playlist{
+	Ok(Playlist{
 		id: playlist_id.clone_static(),
 		sha256: sha256.into(),
-		timestamp: timestamp,
+		tracks_sha256: Some(tracks_sha256),
+		cover_sha256,
+		timestamp,
 		count: CONF_DEFAULT_COUNT,
-		data: Some(fplaylist)
+		metadata: Some(metadata),
+		changes: None,
+		data: Some(fplaylist),
+		cover
+	})
+}
+
+/// Whether the API answered that the requested resource does not exist (anymore).
+pub fn is_not_found(error: &ClientError) -> bool {
+	match error {
+		ClientError::Http(e) => matches!(e.as_ref(), HttpError::StatusCode(r) if r.status() == reqwest::StatusCode::NOT_FOUND),
+		_ => false
+	}
+}
+
+// Covers are served by the CDN of Spotify, not its API, so their downloads are not API calls
+async fn get_playlist_cover(url: &str) -> ClientResult<Vec<u8>>{
+	let res = match reqwest::get(url).await.and_then(|r| r.error_for_status()) {
+		Ok(r) => r.bytes().await,
+		Err(e) => Err(e)
 	};
 
+	match res {
+		Ok(bytes) => {
+			info!("Cover {} retreived ({} bytes).", url, bytes.len());
+			Ok(bytes.to_vec())
+		},
+		Err(e) => {
+			error!("Cover {} cannot be retreived: {}", url, e);
+			Err(HttpError::from(e).into())
+		}
+	}
+}
+
+async fn upload_playlist_cover(client: &AuthCodeSpotify, playlist_id: &PlaylistId<'_>, jpeg: &[u8]) -> ClientResult<()>{
+	let access_token = match client.get_token().lock().await.ok().as_deref().and_then(Option::as_ref) {
+		Some(token) => token.access_token.clone(),
+		None => return Err(ClientError::CacheFile(String::from("No user token to upload the cover with.")))
+	};
 
-	playlist
+	metrics::inc_api_calls();
+	let response = reqwest::Client::new()
+		.put(RSPOTIFY_PLAYLIST_COVER_URL.replace("{}", playlist_id.id()))
+		.bearer_auth(access_token)
+		.header(reqwest::header::CONTENT_TYPE, "image/jpeg")
+		.body(general_purpose::STANDARD.encode(jpeg))
+		.send()
+		.await
+		.map_err(HttpError::from)?;
+
+	if !response.status().is_success(){
+		return Err(HttpError::StatusCode(response).into());
+	}
+
+	info!("Cover of {} uploaded ({} bytes).", playlist_id.id(), jpeg.len());
+	Ok(())
 }
 
-pub async fn export_playlist_to_user(client: &AuthCodeSpotify, playlist: &Playlist){
+pub async fn export_playlist_to_user(client: &AuthCodeSpotify, playlist: &Playlist, name: &str) -> ClientResult<PlaylistId<'static>>{
 	let user_id = playlist.data.as_ref().unwrap().owner.id.clone_static();
 
-	let date = DateTime::<Local>::from(UNIX_EPOCH + Duration::from_secs(playlist.timestamp));
-	let format_date = format!("{}", date.format("%v %X"));	
-	let name = format!(
-		"Archify - {} - {}",
-		playlist.data.as_ref().unwrap().name,
-		format_date
-	);
-
-	let new_p = client.user_playlist_create(user_id, name.as_str(), Some(true), Some(false), Some(RSPOTIFY_PLAYLIST_DESCRIPTION)).await.unwrap();
+	metrics::inc_api_calls();
+	let new_p = client.user_playlist_create(user_id, name, Some(true), Some(false), Some(RSPOTIFY_PLAYLIST_DESCRIPTION)).await?;
 
-	let mut tracks: Vec<PlayableId> = Vec::new();
-	for p in &playlist.data.as_ref().unwrap().tracks.items{
+	let mut items: Vec<PlayableId> = Vec::new();
+	for (position, p) in playlist.data.as_ref().unwrap().tracks.items.iter().enumerate(){
 		match &p.track {
-			Some(item) => match &item.id() {
-				Some(id) => tracks.push(id.clone_static()),
-				None => ()
+			Some(PlayableItem::Track(track)) => match &track.id {
+				Some(id) => items.push(PlayableId::Track(id.clone_static())),
+				None => info!("Item {} of {} cannot be exported: local track.", position, playlist.id.id())
 			},
-			None => ()
+			Some(PlayableItem::Episode(episode)) => items.push(PlayableId::Episode(episode.id.clone_static())),
+			None => info!("Item {} of {} cannot be exported: unavailable.", position, playlist.id.id())
 		}
 	}
 
-	let _ = client.playlist_add_items(new_p.id, tracks , Some(0)).await.unwrap();
+	// The API accepts a limited amount of items per request
+	for chunk in items.chunks(RSPOTIFY_MAX_ITEMS_PER_REQUEST){
+		metrics::inc_api_calls();
+		client.playlist_add_items(new_p.id.clone_static(), chunk.iter().map(|id| id.as_ref()), None).await?;
+	}
+
+	let archived_cover = playlist.cover.as_deref().and_then(cover::prepare_cover);
+	let jpeg = archived_cover.unwrap_or_else(|| {
+		let date = DateTime::<Local>::from(UNIX_EPOCH + Duration::from_secs(playlist.timestamp));
+		cover::generate_cover(&date.format("%Y-%m-%d").to_string(), playlist.id.id())
+	});
+
+	if let Err(e) = upload_playlist_cover(client, &new_p.id, &jpeg).await {
+		error!("Cover of {} cannot be uploaded: {}", new_p.id.id(), e);
+	}
+
+	info!("Playlist {} exported as {}.", playlist.id.id(), new_p.id.id());
 
-}
\ No newline at end of file
+	Ok(new_p.id)
+}
@@ -0,0 +1,271 @@
+use crate::report::{ReportFormat, SeriesFormat};
+use crate::conf::*;
+use crate::database::Observation;
+use crate::diff;
+use crate::items::Item;
+
+use std::collections::{HashMap, HashSet};
+use std::time::{UNIX_EPOCH, Duration};
+
+use chrono::{DateTime, Local};
+use serde::Serialize;
+
+const SPARKLINE_TICKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+fn format_timestamp(timestamp: u64) -> String{
+	let date = DateTime::<Local>::from(UNIX_EPOCH + Duration::from_secs(timestamp));
+	format!("{}", date.format("%v %X"))
+}
+
+fn sparkline(values: &[u32]) -> String{
+	let min = values.iter().min().copied().unwrap_or_default();
+	let max = values.iter().max().copied().unwrap_or_default();
+	let range = (max - min).max(1) as f64;
+
+	values.iter()
+		.map(|v| SPARKLINE_TICKS[(((v - min) as f64 / range) * (SPARKLINE_TICKS.len() - 1) as f64).round() as usize])
+		.collect()
+}
+
+pub fn print_followers(observations: &[Observation], format: SeriesFormat){
+	match format {
+		SeriesFormat::Table => {
+			println!("{: <22} {: >10} {: >8} {: >6}  Snapshot", "Date", "Followers", "Change", "Items");
+			let mut previous: Option<u32> = None;
+			for o in observations {
+				let change = match previous {
+					Some(p) => format!("{:+}", o.followers as i64 - p as i64),
+					None => String::new()
+				};
+				println!("{: <22} {: >10} {: >8} {: >6}  {}", format_timestamp(o.timestamp), o.followers, change, o.item_count, o.snapshot_id);
+				previous = Some(o.followers);
+			}
+		},
+		SeriesFormat::Csv => {
+			println!("timestamp,followers,snapshot_id,item_count");
+			for o in observations {
+				println!("{},{},{},{}", o.timestamp, o.followers, o.snapshot_id, o.item_count);
+			}
+		},
+		SeriesFormat::Sparkline => {
+			let followers: Vec<u32> = observations.iter().map(|o| o.followers).collect();
+			println!("{}", sparkline(&followers));
+			println!(
+				"{} → {}: min {}, max {}, last {}",
+				format_timestamp(observations.first().unwrap().timestamp),
+				format_timestamp(observations.last().unwrap().timestamp),
+				followers.iter().min().unwrap(),
+				followers.iter().max().unwrap(),
+				followers.last().unwrap()
+			);
+		}
+	}
+}
+
+#[derive(Default)]
+struct Contribution {
+	added: u64,
+	removed: u64,
+	present: u64
+}
+
+/// Counts, per user, the items added and removed across `versions`. A removal is attributed to the user who added the item.
+pub fn print_contributors(versions: &[(u64, Vec<Item>)]){
+	let mut contributions: HashMap<String, Contribution> = HashMap::new();
+	let user = |item: &Item| item.added_by.clone().unwrap_or_else(|| String::from("(unknown)"));
+
+	let mut previous: &[Item] = &[];
+	for (_, items) in versions {
+		let d = diff::diff(previous, items);
+		for item in &d.added {
+			contributions.entry(user(item)).or_default().added += 1;
+		}
+		for item in &d.removed {
+			contributions.entry(user(item)).or_default().removed += 1;
+		}
+		previous = items;
+	}
+	for item in previous {
+		contributions.entry(user(item)).or_default().present += 1;
+	}
+
+	let mut contributions: Vec<(String, Contribution)> = contributions.into_iter().collect();
+	contributions.sort_by(|a, b| b.1.added.cmp(&a.1.added).then_with(|| a.0.cmp(&b.0)));
+
+	println!("{: <30} {: >8} {: >8} {: >8}", "User", "Added", "Removed", "Present");
+	for (user, c) in contributions {
+		println!("{: <30} {: >8} {: >8} {: >8}", user, c.added, c.removed, c.present);
+	}
+}
+
+#[derive(Serialize)]
+pub struct ArtistCount {
+	pub artist: String,
+	pub count: u64
+}
+
+/// Track added to the playlist more than once, i.e. recommended again after having left it.
+#[derive(Serialize)]
+pub struct RepeatedTrack {
+	pub uri: String,
+	pub name: String,
+	pub artists: Vec<String>,
+	pub count: u64
+}
+
+#[derive(Serialize)]
+pub struct VersionStats {
+	pub index: u64,
+	pub timestamp: u64,
+	pub items: usize,
+	pub duration_ms: u64,
+	pub added: usize,
+	pub removed: usize
+}
+
+#[derive(Serialize)]
+pub struct YearCount {
+	/// `None` when the release date is unknown.
+	pub year: Option<u32>,
+	pub count: u64
+}
+
+#[derive(Serialize)]
+pub struct PlaylistStats {
+	pub distinct_tracks: usize,
+	pub distinct_artists: usize,
+	/// Artists ordered by the number of times one of their tracks was added.
+	pub top_artists: Vec<ArtistCount>,
+	pub repeated_tracks: Vec<RepeatedTrack>,
+	/// Items added plus items removed, averaged over every version but the first.
+	pub average_churn: f64,
+	pub versions: Vec<VersionStats>,
+	/// Distinct tracks per release year.
+	pub release_years: Vec<YearCount>
+}
+
+/// Computes the report of a playlist from its versions, ascending by timestamp.
+pub fn get_playlist_stats(versions: &[(u64, Vec<Item>)]) -> PlaylistStats {
+	let mut tracks: HashMap<&str, &Item> = HashMap::new();
+	let mut additions: HashMap<String, u64> = HashMap::new();
+	let mut artists: HashMap<String, u64> = HashMap::new();
+	let mut version_stats = Vec::new();
+
+	let mut previous: &[Item] = &[];
+	for (i, (timestamp, items)) in versions.iter().enumerate(){
+		for item in items {
+			tracks.entry(item.uri.as_str()).or_insert(item);
+		}
+		let d = diff::diff(previous, items);
+		for item in &d.added {
+			*additions.entry(item.uri.clone()).or_default() += 1;
+			for artist in &item.artists {
+				*artists.entry(artist.clone()).or_default() += 1;
+			}
+		}
+		version_stats.push(VersionStats {
+			index: i as u64 + 1,
+			timestamp: *timestamp,
+			items: items.len(),
+			duration_ms: items.iter().map(|i| i.duration_ms).sum(),
+			added: d.added.len(),
+			removed: d.removed.len()
+		});
+		previous = items;
+	}
+
+	let distinct_artists = tracks.values().flat_map(|t| t.artists.iter()).collect::<HashSet<_>>().len();
+
+	let mut top_artists: Vec<ArtistCount> = artists.into_iter()
+		.map(|(artist, count)| ArtistCount { artist, count })
+		.collect();
+	top_artists.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.artist.cmp(&b.artist)));
+	top_artists.truncate(CONF_STATS_TOP_ARTISTS);
+
+	let mut repeated_tracks: Vec<RepeatedTrack> = additions.into_iter()
+		.filter(|(_, count)| *count > 1)
+		.map(|(uri, count)| RepeatedTrack {
+			name: tracks[uri.as_str()].name.clone(),
+			artists: tracks[uri.as_str()].artists.clone(),
+			uri,
+			count
+		})
+		.collect();
+	repeated_tracks.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+
+	let churn: Vec<usize> = version_stats.iter().skip(1).map(|v| v.added + v.removed).collect();
+	let average_churn = match churn.is_empty() {
+		true => 0.0,
+		false => churn.iter().sum::<usize>() as f64 / churn.len() as f64
+	};
+
+	let mut release_years: HashMap<Option<u32>, u64> = HashMap::new();
+	for track in tracks.values() {
+		let year = track.release_date.as_deref().and_then(|d| d.get(..4)).and_then(|y| y.parse().ok());
+		*release_years.entry(year).or_default() += 1;
+	}
+	let mut release_years: Vec<YearCount> = release_years.into_iter()
+		.map(|(year, count)| YearCount { year, count })
+		.collect();
+	release_years.sort_by_key(|y| y.year);
+
+	PlaylistStats {
+		distinct_tracks: tracks.len(),
+		distinct_artists,
+		top_artists,
+		repeated_tracks,
+		average_churn,
+		versions: version_stats,
+		release_years
+	}
+}
+
+fn format_duration(duration_ms: u64) -> String{
+	let minutes = duration_ms / 60000;
+	format!("{}h{:02}m", minutes / 60, minutes % 60)
+}
+
+pub fn print_playlist_stats(stats: &PlaylistStats, format: ReportFormat){
+	match format {
+		ReportFormat::Json => println!("{}", serde_json::to_string_pretty(stats).unwrap()),
+		ReportFormat::Text => {
+			println!("Versions: {}", stats.versions.len());
+			println!("Distinct tracks: {}", stats.distinct_tracks);
+			println!("Distinct artists: {}", stats.distinct_artists);
+			println!("Average churn per version: {:.1}", stats.average_churn);
+
+			println!();
+			println!("Most recommended artists:");
+			for a in &stats.top_artists {
+				println!("  {: >4}  {}", a.count, a.artist);
+			}
+
+			println!();
+			println!("Tracks recommended more than once:");
+			if stats.repeated_tracks.is_empty(){
+				println!("  none");
+			}
+			for t in &stats.repeated_tracks {
+				println!("  {: >4}  {} - {}", t.count, t.name, t.artists.join(", "));
+			}
+
+			println!();
+			println!("{: <6} {: <22} {: >6} {: >9} {: >6} {: >8}", "Index", "Date", "Items", "Duration", "Added", "Removed");
+			for v in &stats.versions {
+				println!(
+					"{: <6} {: <22} {: >6} {: >9} {: >6} {: >8}",
+					v.index, format_timestamp(v.timestamp), v.items, format_duration(v.duration_ms), v.added, v.removed
+				);
+			}
+
+			println!();
+			println!("Release years:");
+			let max = stats.release_years.iter().map(|y| y.count).max().unwrap_or(1);
+			for y in &stats.release_years {
+				let year = y.year.map_or(String::from("????"), |y| y.to_string());
+				let bar = "█".repeat((y.count * 40).div_ceil(max) as usize);
+				println!("  {}  {: >4}  {}", year, y.count, bar);
+			}
+		}
+	}
+}
@@ -0,0 +1,335 @@
+use crate::archive::{PlaylistSummary, VersionSummary};
+use crate::diff::Diff;
+use crate::items::{Item, ItemKind};
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use chrono::{DateTime, Local};
+
+pub const STYLE: &str = "
+body { font-family: system-ui, sans-serif; margin: 0; color: #222; background: #fafafa; }
+header { background: #1db954; padding: 0.8em 2em; }
+header a { color: #fff; font-weight: bold; text-decoration: none; font-size: 1.2em; }
+main { padding: 1em 2em; }
+table { border-collapse: collapse; width: 100%; background: #fff; }
+th, td { text-align: left; padding: 0.4em 0.6em; border-bottom: 1px solid #e4e4e4; vertical-align: middle; }
+th { background: #f0f0f0; }
+img.cover { width: 200px; height: 200px; object-fit: cover; border-radius: 4px; }
+img.thumb { width: 48px; height: 48px; object-fit: cover; border-radius: 2px; }
+.version { display: flex; gap: 2em; align-items: flex-start; margin-bottom: 1.5em; }
+.actions { display: flex; gap: 0.5em; flex-wrap: wrap; margin: 0.5em 0; }
+.actions form { margin: 0; }
+.button, button { display: inline-block; padding: 0.3em 0.8em; border: 1px solid #1db954; border-radius: 3px; color: #1db954; background: #fff; text-decoration: none; font-size: 0.9em; cursor: pointer; }
+.added { background: #e6f7ea; }
+.removed { background: #fbe9e9; }
+.muted { color: #888; }
+.flag { font-size: 0.8em; padding: 0.1em 0.4em; border-radius: 3px; background: #eee; margin-left: 0.3em; }
+";
+
+/// Everything shown on the page of a single version.
+pub struct VersionDetail<'a> {
+	pub id: &'a str,
+	pub index: u64,
+	pub timestamp: u64,
+	pub name: &'a str,
+	pub description: Option<&'a str>,
+	pub owner: &'a str,
+	pub followers: u32,
+	pub cover: bool,
+	pub items: &'a [Item],
+	/// Whether no version was recorded after this one.
+	pub latest: bool
+}
+
+pub fn escape(s: &str) -> String {
+	s.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+		.replace('\'', "&#39;")
+}
+
+pub fn format_date(timestamp: u64) -> String {
+	let date = DateTime::<Local>::from(UNIX_EPOCH + Duration::from_secs(timestamp));
+	format!("{}", date.format("%v %X"))
+}
+
+/// Where the pages link to, which differs between the web UI of `serve` and a static site.
+pub trait Links {
+	fn home(&self) -> String;
+	fn stylesheet(&self) -> String;
+	fn playlist(&self, id: &str) -> String;
+	fn version(&self, id: &str, index: u64) -> String;
+	fn cover(&self, id: &str, index: u64) -> String;
+	/// Items of a version, `format` being `csv` or `json`.
+	fn download(&self, id: &str, index: u64, format: &str) -> String;
+	fn diff(&self, id: &str, from: u64, to: u64) -> String;
+	/// Form exporting a version to Spotify, `None` when exports are not available.
+	fn export(&self, id: &str, index: u64) -> Option<String>;
+	/// Form comparing any two versions, `None` when only consecutive versions are compared.
+	fn diff_form(&self, id: &str) -> Option<String>;
+}
+
+/// Links of the web UI served by `serve`.
+pub struct ServerLinks;
+
+impl Links for ServerLinks {
+	fn home(&self) -> String {
+		String::from("/")
+	}
+
+	fn stylesheet(&self) -> String {
+		String::from("/style.css")
+	}
+
+	fn playlist(&self, id: &str) -> String {
+		format!("/ui/playlists/{}", id)
+	}
+
+	fn version(&self, id: &str, index: u64) -> String {
+		format!("/ui/playlists/{}/versions/{}", id, index)
+	}
+
+	fn cover(&self, id: &str, index: u64) -> String {
+		format!("{}/cover", self.version(id, index))
+	}
+
+	fn download(&self, id: &str, index: u64, format: &str) -> String {
+		format!("{}/download?format={}", self.version(id, index), format)
+	}
+
+	fn diff(&self, id: &str, from: u64, to: u64) -> String {
+		format!("/ui/playlists/{}/diff?from={}&to={}", id, from, to)
+	}
+
+	fn export(&self, id: &str, index: u64) -> Option<String> {
+		Some(format!("{}/export", self.version(id, index)))
+	}
+
+	fn diff_form(&self, id: &str) -> Option<String> {
+		Some(format!("/ui/playlists/{}/diff", id))
+	}
+}
+
+fn page(links: &dyn Links, title: &str, body: &str) -> String {
+	format!(
+		"<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n<title>{} - Archify</title>\n<link rel=\"stylesheet\" href=\"{}\">\n</head>\n<body>\n<header><a href=\"{}\">Archify</a></header>\n<main>\n{}\n</main>\n</body>\n</html>\n",
+		escape(title), links.stylesheet(), links.home(), body
+	)
+}
+
+fn format_changes(version: &VersionSummary) -> String {
+	match &version.changes {
+		_ if version.index == 1 => String::from("first version"),
+		Some(changes) if changes.is_empty() => String::from("no visible change"),
+		Some(changes) => changes.join(", "),
+		None => String::from("unknown change")
+	}
+}
+
+fn items_table(items: &[Item], class: &str) -> String {
+	let mut rows = String::new();
+	for item in items {
+		let detail = match item.kind {
+			ItemKind::Episode => item.show.clone().unwrap_or_default(),
+			_ => item.album.clone().unwrap_or_default()
+		};
+		rows.push_str(&format!(
+			"<tr class=\"{}\"><td>{}</td><td>{}{}</td><td>{}</td><td>{}</td><td>{}</td><td class=\"muted\">{}</td></tr>\n",
+			class,
+			item.position + 1,
+			escape(item.marker()),
+			escape(&item.name),
+			escape(&item.creator()),
+			escape(&detail),
+			item.format_duration(),
+			escape(item.added_at.as_deref().unwrap_or_default())
+		));
+	}
+	format!(
+		"<table>\n<tr><th>#</th><th>Title</th><th>Artists</th><th>Album</th><th>Duration</th><th>Added</th></tr>\n{}</table>",
+		rows
+	)
+}
+
+pub fn render_index(links: &dyn Links, playlists: &[PlaylistSummary]) -> String {
+	let mut rows = String::new();
+	for p in playlists {
+		let thumb = match p.cover {
+			true => format!("<img class=\"thumb\" src=\"{}\" alt=\"\">", links.cover(&p.id, p.versions)),
+			false => String::new()
+		};
+		let status = match (p.paused, p.next_due) {
+			(true, _) => String::from("paused"),
+			(false, Some(next_due)) => format!("next update {}", format_date(next_due)),
+			(false, None) => String::from("no next update")
+		};
+		rows.push_str(&format!(
+			"<tr><td>{}</td><td><a href=\"{}\">{}</a></td><td>{}</td><td class=\"muted\">{}</td></tr>\n",
+			thumb,
+			links.playlist(&p.id),
+			escape(p.name.as_deref().unwrap_or(&p.id)),
+			p.versions,
+			status
+		));
+	}
+
+	let body = match playlists.is_empty() {
+		true => String::from("<h1>Playlists</h1>\n<p>No playlist tracked yet.</p>"),
+		false => format!(
+			"<h1>Playlists</h1>\n<table>\n<tr><th></th><th>Name</th><th>Versions</th><th>Status</th></tr>\n{}</table>",
+			rows
+		)
+	};
+	page(links, "Playlists", &body)
+}
+
+pub fn render_timeline(links: &dyn Links, id: &str, versions: &[VersionSummary]) -> String {
+	let name = versions.last().and_then(|v| v.name.as_deref()).unwrap_or(id);
+
+	let mut rows = String::new();
+	for v in versions.iter().rev() {
+		let mut flags = String::new();
+		if v.exported {
+			flags.push_str("<span class=\"flag\">exported</span>");
+		}
+		if v.pinned {
+			flags.push_str("<span class=\"flag\">pinned</span>");
+		}
+		let diff = match v.index {
+			1 => String::new(),
+			i => format!("<a class=\"button\" href=\"{}\">diff</a>", links.diff(id, i - 1, i))
+		};
+		rows.push_str(&format!(
+			"<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}{}</td><td><div class=\"actions\">{}<a class=\"button\" href=\"{}\">CSV</a><a class=\"button\" href=\"{}\">JSON</a></div></td></tr>\n",
+			links.version(id, v.index),
+			v.index,
+			format_date(v.timestamp),
+			escape(&format_changes(v)),
+			flags,
+			diff,
+			links.download(id, v.index, "csv"),
+			links.download(id, v.index, "json")
+		));
+	}
+
+	let body = format!(
+		"<h1>{}</h1>\n<p class=\"muted\">{} version(s)</p>\n<table>\n<tr><th>Version</th><th>Date</th><th>Changes</th><th></th></tr>\n{}</table>",
+		escape(name),
+		versions.len(),
+		rows
+	);
+	page(links, name, &body)
+}
+
+pub fn render_version(links: &dyn Links, v: &VersionDetail) -> String {
+	let cover = match v.cover {
+		true => format!("<img class=\"cover\" src=\"{}\" alt=\"Cover\">", links.cover(v.id, v.index)),
+		false => String::new()
+	};
+	let mut navigation = String::new();
+	if v.index > 1 {
+		navigation.push_str(&format!("<a class=\"button\" href=\"{}\">previous</a>", links.version(v.id, v.index - 1)));
+		navigation.push_str(&format!("<a class=\"button\" href=\"{}\">diff with previous</a>", links.diff(v.id, v.index - 1, v.index)));
+	}
+	if !v.latest {
+		navigation.push_str(&format!("<a class=\"button\" href=\"{}\">next</a>", links.version(v.id, v.index + 1)));
+	}
+	let mut downloads = format!(
+		"<a class=\"button\" href=\"{}\">download CSV</a><a class=\"button\" href=\"{}\">download JSON</a>",
+		links.download(v.id, v.index, "csv"),
+		links.download(v.id, v.index, "json")
+	);
+	if v.cover {
+		downloads.push_str(&format!("<a class=\"button\" href=\"{}\" download>download cover</a>", links.cover(v.id, v.index)));
+	}
+	if let Some(export) = links.export(v.id, v.index) {
+		downloads.push_str(&format!(
+			"<form method=\"post\" action=\"{}\"><button type=\"submit\">export to Spotify</button></form>",
+			export
+		));
+	}
+
+	let body = format!(
+		"<div class=\"version\">{}<div>\n<h1>{}</h1>\n<p>{}</p>\n<p class=\"muted\">Version {} - {} - by {} - {} follower(s) - {} item(s)</p>\n<p><a href=\"{}\">All versions</a></p>\n<div class=\"actions\">{}</div>\n<div class=\"actions\">{}</div>\n</div></div>\n{}",
+		cover,
+		escape(v.name),
+		escape(v.description.unwrap_or_default()),
+		v.index,
+		format_date(v.timestamp),
+		escape(v.owner),
+		v.followers,
+		v.items.len(),
+		links.playlist(v.id),
+		navigation,
+		downloads,
+		items_table(v.items, "")
+	);
+	page(links, &format!("{} - version {}", v.name, v.index), &body)
+}
+
+pub fn render_diff(links: &dyn Links, id: &str, versions: &[VersionSummary], from: u64, to: u64, d: &Diff) -> String {
+	let name = versions.iter().find(|v| v.index == to).and_then(|v| v.name.as_deref()).unwrap_or(id);
+
+	let form = match links.diff_form(id) {
+		Some(action) => {
+			let options = |selected: u64| -> String {
+				versions.iter()
+					.map(|v| format!(
+						"<option value=\"{}\"{}>{} - {}</option>",
+						v.index,
+						if v.index == selected { " selected" } else { "" },
+						v.index,
+						format_date(v.timestamp)
+					))
+					.collect()
+			};
+			format!(
+				"<form method=\"get\" action=\"{}\" class=\"actions\"><select name=\"from\">{}</select> → <select name=\"to\">{}</select><button type=\"submit\">compare</button></form>",
+				action,
+				options(from),
+				options(to)
+			)
+		},
+		None => String::new()
+	};
+
+	let changes = match d.is_empty() {
+		true => String::from("<p>No item added or removed.</p>"),
+		false => format!(
+			"<h2>{} added</h2>\n{}\n<h2>{} removed</h2>\n{}",
+			d.added.len(),
+			items_table(&d.added, "added"),
+			d.removed.len(),
+			items_table(&d.removed, "removed")
+		)
+	};
+
+	let body = format!(
+		"<h1>{}</h1>\n<p><a href=\"{}\">All versions</a> - <a href=\"{}\">version {}</a> → <a href=\"{}\">version {}</a></p>\n{}\n{}",
+		escape(name),
+		links.playlist(id),
+		links.version(id, from),
+		from,
+		links.version(id, to),
+		to,
+		form,
+		changes
+	);
+	page(links, &format!("{} - diff {} → {}", name, from, to), &body)
+}
+
+/// Form sending the token of `serve` to `action`, which sets it as a cookie.
+pub fn render_login(links: &dyn Links, action: &str, error: Option<&str>) -> String {
+	let error = error.map(|e| format!("<p>{}</p>\n", escape(e))).unwrap_or_default();
+	let body = format!(
+		"<h1>Login</h1>\n{}<form method=\"post\" action=\"{}\" class=\"actions\"><input type=\"password\" name=\"token\" placeholder=\"Token\" autofocus><button type=\"submit\">log in</button></form>",
+		error,
+		action
+	);
+	page(links, "Login", &body)
+}
+
+pub fn render_error(links: &dyn Links, message: &str) -> String {
+	page(links, "Error", &format!("<h1>Error</h1>\n<p>{}</p>\n<p><a href=\"{}\">Back to the playlists</a></p>", escape(message), links.home()))
+}